@@ -0,0 +1,53 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use crate::WebhookMessage;
+
+/// Where the background worker writes the messages it could not deliver, so they are not lost during
+/// an outage.
+///
+/// Every message is written as its serialized payload on a line of its own (JSON Lines, for the
+/// built-in layers), once its delivery failed for good: after the retry policy is exhausted, when the
+/// endpoint rejected it, or when the circuit breaker dropped it.
+pub struct FallbackWriter {
+    writer: Box<dyn Write + Send>,
+}
+
+impl FallbackWriter {
+    /// Write undelivered messages to the given writer.
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Box::new(writer),
+        }
+    }
+
+    /// Write undelivered messages to the standard error stream.
+    pub fn stderr() -> Self {
+        Self::new(std::io::stderr())
+    }
+
+    /// Append undelivered messages to a file, which is created if it does not exist.
+    pub fn file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(file))
+    }
+
+    pub(crate) fn write(&mut self, message: &dyn WebhookMessage) {
+        let mut line = message.serialize();
+        line.push('\n');
+        let written = self.writer.write_all(line.as_bytes()).and_then(|_| self.writer.flush());
+        if let Err(e) = written {
+            println!(
+                "ERROR: failed to write undelivered webhook message to the fallback: {}",
+                e
+            );
+        }
+    }
+}
+
+impl std::fmt::Debug for FallbackWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FallbackWriter").finish_non_exhaustive()
+    }
+}
@@ -1,3 +1,5 @@
+use std::convert::TryFrom;
+
 use regex::Regex;
 
 pub trait Filter {
@@ -8,6 +10,9 @@ pub trait Filter {
 ///
 /// If provided, each expression is used in either negatively ("does NOT MATCH") or
 /// positively ("does MATCH") filter against a specified value.
+///
+/// The negative list is evaluated first: a value matching any negative expression is excluded. If a
+/// positive list is provided, the value must then match at least one of its expressions.
 #[derive(Debug, Clone, Default)]
 pub struct EventFilters {
     /// An optional list of one-or-more regular expressions to use for determining record inclusion.
@@ -21,6 +26,51 @@ impl EventFilters {
     pub fn new(positive: Option<Vec<Regex>>, negative: Option<Vec<Regex>>) -> Self {
         Self { positive, negative }
     }
+
+    /// Compile a set of matches from regular expression patterns, returning an error for the first
+    /// pattern which is not a valid regular expression.
+    ///
+    /// An empty list of patterns is treated as no filter at all.
+    pub fn from_patterns(positive: &[&str], negative: &[&str]) -> Result<Self, regex::Error> {
+        Ok(Self::new(compile(positive)?, compile(negative)?))
+    }
+
+    /// Compile a set of matches from glob patterns, where `*` matches any sequence of characters and
+    /// `?` matches any single character (e.g. `my_app::*` matches `my_app::http::access_log`).
+    ///
+    /// Unlike regular expressions, a glob must match the entire value.
+    pub fn from_globs(positive: &[&str], negative: &[&str]) -> Result<Self, regex::Error> {
+        let positive = positive.iter().map(|glob| glob_to_regex(glob)).collect::<Vec<_>>();
+        let negative = negative.iter().map(|glob| glob_to_regex(glob)).collect::<Vec<_>>();
+        Self::from_patterns(
+            &positive.iter().map(String::as_str).collect::<Vec<_>>(),
+            &negative.iter().map(String::as_str).collect::<Vec<_>>(),
+        )
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+fn compile(patterns: &[&str]) -> Result<Option<Vec<Regex>>, regex::Error> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    patterns
+        .iter()
+        .map(|pattern| Regex::new(pattern))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
 }
 
 impl Filter for EventFilters {
@@ -33,10 +83,8 @@ impl Filter for EventFilters {
             }
         }
         if let Some(positive) = &self.positive {
-            for filter in positive {
-                if !filter.is_match(value) {
-                    return Err(FilterError::PositiveFilterFailed);
-                }
+            if !positive.iter().any(|filter| filter.is_match(value)) {
+                return Err(FilterError::PositiveFilterFailed);
             }
         }
         Ok(())
@@ -50,6 +98,15 @@ impl From<Regex> for EventFilters {
     }
 }
 
+/// Compile and interpret a single pattern as a single positive filter and no negative filter.
+impl TryFrom<&str> for EventFilters {
+    type Error = regex::Error;
+
+    fn try_from(positive: &str) -> Result<Self, Self::Error> {
+        Ok(Regex::new(positive)?.into())
+    }
+}
+
 /// Interpret and convert a pair of regex as a single positive filter and a single negative filter.
 impl From<(Option<Regex>, Option<Regex>)> for EventFilters {
     fn from((single_positive, single_negative): (Option<Regex>, Option<Regex>)) -> Self {
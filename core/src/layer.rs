@@ -1,30 +1,59 @@
-use std::collections::HashMap;
+use std::backtrace::Backtrace;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
+use rand::Rng;
 use regex::Regex;
 use serde::ser::SerializeMap;
 use serde::Serializer;
 use serde_json::Value;
-use tokio::sync::Mutex;
-use tracing::{Event, Subscriber};
-use tracing::log::LevelFilter;
-use tracing_bunyan_formatter::JsonStorage;
+use sha2::{Digest, Sha256};
+use tracing::callsite::Identifier;
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Id, Level, Metadata, Subscriber};
+use tracing_subscriber::filter::{Filtered, Targets};
+use tracing_subscriber::layer::{Context, Filter as LayerFilter};
 use tracing_subscriber::Layer;
-use tracing_subscriber::layer::Context;
 
-use crate::{BackgroundWorker, ChannelSender, Config, EventFilters, WebhookMessageFactory, WebhookMessageInputs, WorkerMessage};
 use crate::filters::{Filter, FilterError};
-use crate::worker::worker;
+use crate::panic::PANIC_TARGET;
+#[cfg(feature = "blocking")]
+use crate::runtime::ThreadRuntime;
+use crate::runtime::{self, Runtime};
+#[cfg(feature = "blocking")]
+use crate::sink::BlockingWebhookSink;
+use crate::sink::{self as sinks, RoutingSink};
+use crate::storage::FieldStorage;
+use crate::worker::{
+    worker, Backpressure, DeliveredHook, DeliveryErrorHook, DeliveryStats, EventRenderer, Fingerprint, NoticeRenderer,
+    QueueGauge, WorkerOptions, WorkerTask,
+};
+use crate::{
+    BackgroundWorker, ChannelSender, Config, DeliveryError, EventFilters, EventPredicate, FallbackWriter, FieldMap,
+    MessageThread, Notice, NoticeInputs, RetryPolicy, Scrubber, Sink, SpanSummary, WebhookMessage,
+    WebhookMessageFactory, WebhookMessageInputs, WorkerGuard, WorkerMessage,
+};
 
 /// Layer for forwarding tracing events to webhook endpoints.
-pub struct WebhookLayer<C: Config, F: WebhookMessageFactory> {
-    /// Filter events by their target.
-    ///
-    /// Filter type semantics:
-    /// - Subtractive: Exclude an event if the target does NOT MATCH a given regex.
-    /// - Additive: Exclude an event if the target MATCHES a given regex.
-    target_filters: EventFilters,
+///
+/// The layer composes with other layers like any other [`Layer`], including per-layer filters, e.g.
+/// `registry.with(layer.with_filter(LevelFilter::WARN))`. A per-layer filter is evaluated before
+/// `on_event` runs, and only affects this layer: other layers in the subscriber still observe every
+/// event. Note that spans disabled by the filter are not visible to this layer either, so their fields
+/// are not included in the messages it sends.
+pub struct WebhookLayer<C: Config, F: WebhookMessageFactory<C>> {
+    /// The level and target filters, and the callsites they reject, shared with the layer's
+    /// [`FilterHandle`] so they can be replaced at runtime.
+    callsite_filters: Arc<RwLock<CallsiteFilters>>,
 
     /// Filter events by their message.
     ///
@@ -40,88 +69,646 @@ pub struct WebhookLayer<C: Config, F: WebhookMessageFactory> {
     /// - Negative: Exclude the event if its key does NOT MATCH a given regex.
     event_by_field_filters: Option<EventFilters>,
 
-    /// Filter fields of events from being sent to Discord.
+    /// Filter fields of events from being sent to the webhook.
     ///
     /// Filter type semantics:
     /// - Positive: Exclude event fields if the field's key MATCHES any provided regular expressions.
     field_exclusion_filters: Option<Vec<Regex>>,
 
-    /// Filter events by their level.
-    level_filter: Option<String>,
+    /// The only fields which may be sent, if set.
+    field_allowlist: Option<HashSet<String>>,
+
+    /// Fields which are never sent.
+    field_denylist: HashSet<String>,
+
+    /// The names fields are rendered with, if they should not be rendered with their own name.
+    field_renames: HashMap<String, String>,
+
+    /// The maximum number of characters of a rendered field value, and the suffix replacing the rest.
+    max_field_length: Option<(usize, String)>,
+
+    /// Scrubs secrets from the message and field values of events.
+    scrubber: Option<Scrubber>,
+
+    /// Fields whose values are replaced by their salted SHA-256 hash.
+    hashed_fields: HashSet<String>,
+
+    /// Fields which are rendered before all other fields, in this order.
+    priority_fields: Vec<String>,
+
+    /// The salt prepended to the values of hashed fields.
+    field_hash_salt: String,
+
+    /// Capture a backtrace for every ERROR event.
+    capture_backtraces: bool,
+
+    /// Send one in this many events of a target, including its submodules.
+    target_sample_rates: Vec<(String, u32)>,
+
+    /// Send one in this many events of a level, unless their target has a sample rate.
+    level_sample_rates: HashMap<Level, u32>,
+
+    /// Filter events using a user-provided predicate over their metadata and fields.
+    event_predicate: Option<EventPredicate>,
+
+    /// Whether events are sent at all, toggled at runtime through the layer's [`LayerSwitch`].
+    switch: LayerSwitch,
+
+    /// Whether events are dropped, toggled through [`BackgroundWorker::set_muted`].
+    muted: Arc<AtomicBool>,
 
     app_name: String,
 
-    /// Configure the layer's connection to the Webhook API.
-    config: C,
+    /// Configure the layer's connection to the Webhook API, shared with the worker to announce notices.
+    config: Arc<C>,
 
     factory: std::marker::PhantomData<F>,
 
     /// An unbounded sender, which the caller must send `WorkerMessage::Shutdown` in order to cancel
     /// worker's receive-send loop.
     sender: ChannelSender,
+
+    /// Bounds the number of events queued for the worker, if set.
+    backpressure: Option<Arc<Backpressure>>,
+
+    /// Tracks the events queued for the worker.
+    queue: Arc<QueueGauge>,
 }
 
-impl<C: Config, F: WebhookMessageFactory> WebhookLayer<C, F> {
-    /// Create a new layer for forwarding messages to Discord, using a specified
-    /// configuration. This method spawns a task onto the tokio runtime to begin sending tracing
-    /// events to Discord.
-    ///
-    /// Returns the tracing_subscriber::Layer impl to add to a registry, an unbounded-mpsc sender
-    /// used to shutdown the background worker, and a future to spawn as a task on a tokio runtime
-    /// to initialize the worker's processing and sending of HTTP requests to the Discord API.
-    pub(crate) fn new(
-        app_name: String,
-        target_filters: EventFilters,
-        message_filters: Option<EventFilters>,
-        event_by_field_filters: Option<EventFilters>,
-        field_exclusion_filters: Option<Vec<Regex>>,
-        level_filter: Option<String>,
-        config: C,
-    ) -> (WebhookLayer<C, F>, BackgroundWorker) {
-        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+impl<C: Config, F: WebhookMessageFactory<C>> WebhookLayer<C, F> {
+    /// Create a new layer for forwarding events to a webhook endpoint, using the builder's
+    /// configuration, and the background worker sending its messages.
+    ///
+    /// `spawn` starts the worker's future, e.g. as a task on the builder's runtime, which renders the
+    /// layer's events and sends the HTTP requests to the webhook.
+    pub(crate) fn new<W>(builder: WebhookLayerBuilder<C, F>, spawn: W) -> (WebhookLayer<C, F>, BackgroundWorker)
+    where
+        W: FnOnce(Pin<Box<dyn Future<Output = ()> + Send>>) -> WorkerTask,
+        C: Send + Sync + 'static,
+        F: 'static,
+    {
+        let (tx, rx) = async_channel::unbounded();
+        let stats = Arc::new(DeliveryStats::default());
         let layer = WebhookLayer {
-            target_filters,
-            message_filters,
-            field_exclusion_filters,
-            event_by_field_filters,
-            level_filter,
-            app_name,
-            config,
+            callsite_filters: Arc::new(RwLock::new(CallsiteFilters {
+                filters: MetadataFilters {
+                    level_filter: builder.level_filter,
+                    target_directives: builder.target_directives,
+                    target_filters: builder.target_filters,
+                    config_level_filter: None,
+                    config_target_directives: None,
+                },
+                notify_field: Self::reserved_field("notify"),
+                callsites: HashMap::new(),
+                disabled: HashSet::new(),
+                notify: HashSet::new(),
+            })),
+            message_filters: builder.message_filters,
+            field_exclusion_filters: builder.field_exclusion_filters,
+            field_allowlist: builder.field_allowlist,
+            field_denylist: builder.field_denylist,
+            field_renames: builder.field_renames,
+            max_field_length: builder.max_field_length,
+            scrubber: builder.scrubber,
+            hashed_fields: builder.hashed_fields,
+            priority_fields: builder.priority_fields,
+            field_hash_salt: builder.field_hash_salt,
+            capture_backtraces: builder.capture_backtraces,
+            target_sample_rates: builder.target_sample_rates,
+            level_sample_rates: builder.level_sample_rates,
+            event_by_field_filters: builder.event_by_field_filters,
+            event_predicate: builder.event_predicate,
+            switch: LayerSwitch::default(),
+            muted: Arc::new(AtomicBool::new(false)),
+            app_name: builder.app_name,
+            config: Arc::new(builder.config.unwrap_or_else(C::new_from_env)),
             factory: Default::default(),
             sender: tx.clone(),
+            backpressure: builder
+                .backpressure
+                .map(|capacity| Arc::new(Backpressure::new(capacity, stats.clone()))),
+            queue: Arc::new(QueueGauge::default()),
+        };
+        layer.config.apply_filters(layer.filter_handle());
+        let http_client = builder.http_client;
+        let mut sink = builder
+            .sink
+            .clone()
+            .or_else(|| config_sink(&*layer.config, http_client.clone()))
+            .unwrap_or_else(sinks::default_sink);
+        let mut escalations = Vec::new();
+        let mut routes = Vec::new();
+        for escalation in builder.escalations {
+            escalations.push((escalation.level, escalation.render));
+            routes.push((escalation.webhook_urls, (escalation.sink)(http_client.clone())));
+        }
+        if builder.sink.is_none() && !routes.is_empty() {
+            sink = Arc::new(RoutingSink { default: sink, routes });
+        }
+        let events: EventRenderer = {
+            let config = layer.config.clone();
+            Arc::new(move |batch| {
+                let urls = config.webhook_urls();
+                let mut messages: Vec<_> = urls
+                    .iter()
+                    .flat_map(|webhook_url| {
+                        let batch = batch
+                            .iter()
+                            .map(|inputs| WebhookMessageInputs {
+                                webhook_url: webhook_url.to_string(),
+                                ..inputs.clone()
+                            })
+                            .collect();
+                        F::create_batch(&config, batch)
+                    })
+                    .collect();
+                for inputs in &batch {
+                    for (level, render) in &escalations {
+                        if inputs.event_level <= *level {
+                            messages.extend(render(inputs));
+                        }
+                    }
+                }
+                messages
+            })
+        };
+        let notices: NoticeRenderer = {
+            let config = layer.config.clone();
+            let app_name = layer.app_name.clone();
+            let host = hostname();
+            Arc::new(move |notice| {
+                let inputs = |webhook_url: &str| NoticeInputs {
+                    app_name: app_name.clone(),
+                    notice: notice.clone(),
+                    host: host.clone(),
+                    webhook_url: webhook_url.to_string(),
+                };
+                config
+                    .webhook_urls()
+                    .into_iter()
+                    .filter_map(|webhook_url| F::notice(&config, inputs(webhook_url)))
+                    .collect()
+            })
+        };
+        for message in notices(&Notice::Startup) {
+            let _ = tx.try_send(WorkerMessage::Data(message));
+        }
+        let options = WorkerOptions {
+            heartbeat_interval: layer.config.heartbeat_interval(),
+            rate_limit: builder.rate_limit.or_else(|| layer.config.rate_limit()),
+            dedup_window: builder.dedup_window,
+            cooldown: builder.cooldown,
+            digest: builder.digest,
+            batch: builder.batch,
+            fingerprint: builder
+                .fingerprint
+                .unwrap_or_else(|| Arc::new(WebhookMessageInputs::fingerprint)),
+            retry: builder.retry,
+            circuit_breaker: builder.circuit_breaker,
+            fallback: builder.fallback,
+            persistent_queue: builder.persistent_queue,
+            backpressure: layer.backpressure.clone(),
+            queue: layer.queue.clone(),
+            prioritize_errors: builder.prioritize_errors,
+            concurrency: builder.concurrency,
+            stats: stats.clone(),
+            on_delivery_error: builder.on_delivery_error,
+            on_delivered: builder.on_delivered,
+            runtime: builder.runtime.clone(),
         };
         let worker = BackgroundWorker {
             sender: tx,
-            handle: Arc::new(Mutex::new(Some(tokio::spawn(worker(rx))))),
+            stats,
+            queue: layer.queue.clone(),
+            runtime: builder.runtime,
+            muted: layer.muted.clone(),
+            handle: Arc::new(Mutex::new(Some(spawn(Box::pin(worker(
+                rx, sink, events, notices, options,
+            )))))),
         };
         (layer, worker)
     }
 
-    /// Create a new builder for DiscordLayer.
+    /// Whether an event is sampled to be sent, according to the sample rate of its target, or else of its
+    /// level.
+    fn sampled(&self, metadata: &Metadata<'_>) -> bool {
+        let target = metadata.target();
+        // The most specific target takes precedence.
+        let one_in = self
+            .target_sample_rates
+            .iter()
+            .filter(|(prefix, _)| {
+                target == prefix
+                    || target
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|&(_, one_in)| one_in)
+            .or_else(|| self.level_sample_rates.get(metadata.level()).copied());
+        match one_in {
+            Some(one_in) if one_in > 1 && target != PANIC_TARGET => rand::thread_rng().gen_ratio(1, one_in),
+            _ => true,
+        }
+    }
+
+    /// The full name of a reserved field (e.g. `slack.notify` for `notify`), if the factory reserves
+    /// fields.
+    fn reserved_field(name: &str) -> Option<String> {
+        F::RESERVED_FIELD_PREFIX.map(|prefix| format!("{}{}", prefix, name))
+    }
+
+    /// Whether an event sets a reserved flag to `true`.
+    fn event_flag(event_fields: &FieldStorage, name: &str) -> bool {
+        let field = Self::reserved_field(name);
+        field.and_then(|field| event_fields.values().get(field.as_str()).cloned()) == Some(Value::Bool(true))
+    }
+
+    /// The name a field is rendered with.
+    fn display_name<'a>(&'a self, field: &'a str) -> &'a str {
+        self.field_renames.get(field).map(String::as_str).unwrap_or(field)
+    }
+
+    /// The value a field is sent with: hashed if it is a hashed field, and scrubbed otherwise.
+    fn anonymize<'a>(&self, field: &str, value: &'a Value) -> Cow<'a, Value> {
+        if self.hashed_fields.contains(field) {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                value => value.to_string(),
+            };
+            let hash = Sha256::new()
+                .chain_update(&self.field_hash_salt)
+                .chain_update(value)
+                .finalize();
+            let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+            return Cow::Owned(Value::String(hex));
+        }
+        match &self.scrubber {
+            Some(scrubber) => Cow::Owned(scrubber.scrub_value(value)),
+            None => Cow::Borrowed(value),
+        }
+    }
+
+    /// The value a field is rendered with, anonymized and truncated to the maximum field length.
+    fn display_value<'a>(&self, field: &str, value: &'a Value) -> Cow<'a, Value> {
+        self.truncate(self.anonymize(field, value))
+    }
+
+    /// A value truncated to the maximum field length.
+    fn truncate<'a>(&self, value: Cow<'a, Value>) -> Cow<'a, Value> {
+        match (value.as_ref(), &self.max_field_length) {
+            (Value::String(s), Some((max_length, suffix))) if s.chars().count() > *max_length => {
+                let truncated: String = s.chars().take(*max_length).collect();
+                Cow::Owned(Value::String(truncated + suffix))
+            }
+            _ => value,
+        }
+    }
+
+    /// Whether a field may be sent, according to the field allowlist and denylist.
+    fn field_allowed(&self, field: &str) -> bool {
+        let allowed = match &self.field_allowlist {
+            Some(field_allowlist) => field_allowlist.contains(field),
+            None => true,
+        };
+        allowed && !self.field_denylist.contains(field)
+    }
+
+    /// The switch turning the layer on and off at runtime, e.g. from an admin endpoint, without
+    /// rebuilding the subscriber. The layer starts out enabled.
+    pub fn switch(&self) -> LayerSwitch {
+        self.switch.clone()
+    }
+
+    /// The handle replacing the layer's level filter, target directives, and target filters at runtime,
+    /// like [`tracing_subscriber::reload`] does for whole layers.
+    pub fn filter_handle(&self) -> FilterHandle {
+        FilterHandle {
+            callsite_filters: self.callsite_filters.clone(),
+        }
+    }
+
+    /// Wrap the layer in its own level and target filters as a per-layer filter, so the callsites they
+    /// reject are disabled for this layer once, when they are registered, and their events never reach
+    /// `on_event`. Other layers of the subscriber are unaffected.
+    ///
+    /// The filters can still be replaced through the layer's [`FilterHandle`].
+    pub fn filtered<S>(self) -> Filtered<Self, FilterHandle, S>
+    where
+        S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+        C: 'static,
+        F: 'static,
+    {
+        let filter = self.filter_handle();
+        self.with_filter(filter)
+    }
+
+    /// Create a new builder for a webhook layer.
     pub fn builder(app_name: String, target_filters: EventFilters) -> WebhookLayerBuilder<C, F> {
         WebhookLayerBuilder::new(app_name, target_filters)
     }
 }
 
+/// The filters which only depend on the level and target of events.
+#[derive(Debug)]
+struct MetadataFilters {
+    /// Filter events by their level.
+    level_filter: Option<LevelFilter>,
+
+    /// Filter events by per-target level directives (e.g. `my_app=warn,my_app::payments=info`).
+    target_directives: Option<Targets>,
+
+    /// Filter events by their target.
+    ///
+    /// Filter type semantics:
+    /// - Positive: Exclude an event if the target does NOT MATCH a given regex.
+    /// - Negative: Exclude an event if the target MATCHES a given regex.
+    target_filters: EventFilters,
+
+    /// The level filter of the layer's configuration, e.g. loaded from a configuration file, which applies
+    /// in addition to the layer's own.
+    config_level_filter: Option<LevelFilter>,
+
+    /// The target directives of the layer's configuration, which apply in addition to the layer's own.
+    config_target_directives: Option<Targets>,
+}
+
+impl MetadataFilters {
+    /// Whether an event with the given metadata passes the filters.
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        for level_filter in [self.level_filter, self.config_level_filter].iter().flatten() {
+            if metadata.level() > level_filter {
+                return false;
+            }
+        }
+        for target_directives in [&self.target_directives, &self.config_target_directives]
+            .iter()
+            .copied()
+            .flatten()
+        {
+            if !target_directives.would_enable(metadata.target(), metadata.level()) {
+                return false;
+            }
+        }
+        // Panics are reported regardless of the module they occur in.
+        metadata.target() == PANIC_TARGET || self.target_filters.process(metadata.target()).is_ok()
+    }
+}
+
+/// The level and target filters of a layer, and the callsites of events they reject.
+///
+/// The filters are evaluated once when a callsite is registered. Every registered event callsite is
+/// kept, so the callsites can be evaluated again when the filters are replaced.
+#[derive(Debug)]
+struct CallsiteFilters {
+    filters: MetadataFilters,
+
+    /// The reserved field by which events may force being sent, if the factory reserves fields.
+    notify_field: Option<String>,
+
+    /// The metadata of every registered event callsite.
+    callsites: HashMap<Identifier, &'static Metadata<'static>>,
+
+    /// Callsites of events which are rejected by the filters.
+    disabled: HashSet<Identifier>,
+
+    /// Callsites of events which are rejected by the filters, but declare the reserved `notify` field, so
+    /// their events are sent if they set it to `true`.
+    notify: HashSet<Identifier>,
+}
+
+impl CallsiteFilters {
+    /// Register an event callsite, recording whether the filters reject its events.
+    fn register(&mut self, metadata: &'static Metadata<'static>) {
+        self.callsites.insert(metadata.callsite(), metadata);
+        self.evaluate(metadata);
+    }
+
+    /// Record whether the filters reject the events of a callsite.
+    fn evaluate(&mut self, metadata: &'static Metadata<'static>) {
+        if self.filters.enabled(metadata) {
+            return;
+        }
+        // Events which declare the `notify` field may force being sent.
+        match &self.notify_field {
+            Some(notify_field) if metadata.fields().field(notify_field).is_some() => {
+                self.notify.insert(metadata.callsite())
+            }
+            _ => self.disabled.insert(metadata.callsite()),
+        };
+    }
+
+    /// Replace the filters, evaluating every registered callsite again.
+    fn replace(&mut self, replace: impl FnOnce(&mut MetadataFilters)) {
+        replace(&mut self.filters);
+        self.disabled.clear();
+        self.notify.clear();
+        let callsites: Vec<_> = self.callsites.values().copied().collect();
+        for metadata in callsites {
+            self.evaluate(metadata);
+        }
+    }
+}
+
+/// A shared handle replacing the level and target filters of a [`WebhookLayer`] at runtime, e.g. to
+/// send DEBUG events while investigating an incident.
+///
+/// Replacing the filters takes effect for the next event of every callsite, without rebuilding the
+/// subscriber or affecting other layers. Events already sent to the worker are not filtered again.
+#[derive(Clone, Debug)]
+pub struct FilterHandle {
+    callsite_filters: Arc<RwLock<CallsiteFilters>>,
+}
+
+impl FilterHandle {
+    /// Replace the level filter, as set with [`WebhookLayerBuilder::level_filter`].
+    pub fn set_level_filter(&self, level_filter: Option<LevelFilter>) {
+        self.replace(|filters| filters.level_filter = level_filter);
+    }
+
+    /// Replace the target directives, as set with [`WebhookLayerBuilder::target_directives`].
+    pub fn set_target_directives(&self, directives: Option<Targets>) {
+        self.replace(|filters| filters.target_directives = directives);
+    }
+
+    /// Replace the target filters the layer was built with.
+    pub fn set_target_filters(&self, target_filters: EventFilters) {
+        self.replace(|filters| filters.target_filters = target_filters);
+    }
+
+    /// Replace the level filter and target directives of the layer's configuration, which apply in addition
+    /// to the layer's own, e.g. when a configuration file setting them is reloaded.
+    pub fn set_config_filters(&self, level_filter: Option<LevelFilter>, target_directives: Option<Targets>) {
+        self.replace(|filters| {
+            filters.config_level_filter = level_filter;
+            filters.config_target_directives = target_directives;
+        });
+    }
+
+    /// The current level filter.
+    pub fn level_filter(&self) -> Option<LevelFilter> {
+        self.callsite_filters
+            .read()
+            .ok()
+            .and_then(|filters| filters.filters.level_filter)
+    }
+
+    fn replace(&self, replace: impl FnOnce(&mut MetadataFilters)) {
+        if let Ok(mut callsite_filters) = self.callsite_filters.write() {
+            callsite_filters.replace(replace);
+        }
+        // Callsites disabled by the previous filters are registered again, for a layer wrapped in the
+        // handle as a per-layer filter.
+        tracing::callsite::rebuild_interest_cache();
+    }
+}
+
+/// As a per-layer filter (see [`WebhookLayer::filtered`]), the handle disables the callsites of events
+/// rejected by the layer's level and target filters, so they never reach the layer.
+impl<S> LayerFilter<S> for FilterHandle {
+    fn enabled(&self, metadata: &Metadata<'_>, _: &Context<'_, S>) -> bool {
+        match self.callsite_filters.read() {
+            Ok(callsite_filters) => !callsite_filters.disabled.contains(&metadata.callsite()),
+            Err(_) => true,
+        }
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if !metadata.is_event() {
+            return Interest::always();
+        }
+        match self.callsite_filters.write() {
+            Ok(mut callsite_filters) => {
+                callsite_filters.register(metadata);
+                if callsite_filters.disabled.contains(&metadata.callsite()) {
+                    Interest::never()
+                } else {
+                    Interest::always()
+                }
+            }
+            Err(_) => Interest::always(),
+        }
+    }
+}
+
+/// A shared switch turning a [`WebhookLayer`] on and off at runtime.
+///
+/// While the layer is disabled, its events are discarded before any of their fields are recorded, so a
+/// disabled layer costs a single atomic load per event.
+#[derive(Clone, Debug)]
+pub struct LayerSwitch {
+    enabled: Arc<AtomicBool>,
+}
+
+impl LayerSwitch {
+    pub fn enable(&self) {
+        self.set_enabled(true);
+    }
+
+    pub fn disable(&self) {
+        self.set_enabled(false);
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for LayerSwitch {
+    fn default() -> Self {
+        Self {
+            enabled: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+/// Renders the messages escalating an event with another factory.
+type EscalationRenderer = Arc<dyn Fn(&WebhookMessageInputs) -> Vec<Box<dyn WebhookMessage>> + Send + Sync>;
+
+/// Events of a level and more severe levels, also sent with another factory and its configuration.
+struct Escalation {
+    level: Level,
+    render: EscalationRenderer,
+    /// The webhook URLs of the escalation's configuration, whose messages are delivered through its sink.
+    webhook_urls: Vec<String>,
+    /// Builds the escalation's sink, sending its requests with the builder's HTTP client, if any.
+    sink: Box<dyn FnOnce(Option<HttpClient>) -> Arc<dyn Sink> + Send>,
+}
+
+/// The HTTP client the builder sends requests with, if it is given one. Only reqwest's async client can be
+/// given, so there is none without the `tokio` feature.
+#[cfg(feature = "tokio")]
+type HttpClient = reqwest::Client;
+#[cfg(not(feature = "tokio"))]
+#[derive(Clone)]
+enum HttpClient {}
+
+/// The transport a configuration requires, sending its requests with the builder's HTTP client, if any.
+fn config_sink<C: Config>(config: &C, http_client: Option<HttpClient>) -> Option<Arc<dyn Sink>> {
+    match http_client {
+        #[cfg(feature = "tokio")]
+        Some(client) => Some(config.sink_with_client(client)),
+        #[cfg(not(feature = "tokio"))]
+        Some(client) => match client {},
+        None => config.sink(),
+    }
+}
+
 /// A builder for creating a webhook layer.
 ///
-/// The layer requires a regex for selecting events to be sent to Discord by their target. Specifying
+/// The layer requires a regex for selecting events to be sent to the webhook by their target. Specifying
 /// no filter (e.g. ".*") will cause an explosion in the number of messages observed by the layer.
 ///
-/// Several methods expose initialization of optional filtering mechanisms, along with Discord
+/// Several methods expose initialization of optional filtering mechanisms, along with the webhook's
 /// configuration that defaults to searching in the local environment variables.
-pub struct WebhookLayerBuilder<C: Config, F: WebhookMessageFactory> {
+pub struct WebhookLayerBuilder<C: Config, F: WebhookMessageFactory<C>> {
     factory: std::marker::PhantomData<F>,
     app_name: String,
     target_filters: EventFilters,
     message_filters: Option<EventFilters>,
     event_by_field_filters: Option<EventFilters>,
     field_exclusion_filters: Option<Vec<Regex>>,
-    level_filters: Option<String>,
+    field_allowlist: Option<HashSet<String>>,
+    field_denylist: HashSet<String>,
+    field_renames: HashMap<String, String>,
+    max_field_length: Option<(usize, String)>,
+    scrubber: Option<Scrubber>,
+    hashed_fields: HashSet<String>,
+    field_hash_salt: String,
+    priority_fields: Vec<String>,
+    capture_backtraces: bool,
+    target_sample_rates: Vec<(String, u32)>,
+    level_sample_rates: HashMap<Level, u32>,
+    rate_limit: Option<(u32, Duration)>,
+    dedup_window: Option<Duration>,
+    cooldown: Option<Duration>,
+    digest: Option<(Duration, Level)>,
+    batch: Option<(usize, Duration)>,
+    fingerprint: Option<Fingerprint>,
+    retry: RetryPolicy,
+    circuit_breaker: Option<(u32, Duration)>,
+    fallback: Option<FallbackWriter>,
+    persistent_queue: Option<PathBuf>,
+    backpressure: Option<usize>,
+    prioritize_errors: bool,
+    concurrency: usize,
+    level_filter: Option<LevelFilter>,
+    target_directives: Option<Targets>,
+    event_predicate: Option<EventPredicate>,
     config: Option<C>,
+    escalations: Vec<Escalation>,
+    sink: Option<Arc<dyn Sink>>,
+    http_client: Option<HttpClient>,
+    on_delivery_error: Option<DeliveryErrorHook>,
+    on_delivered: Option<DeliveredHook>,
+    runtime: Arc<dyn Runtime>,
 }
 
-impl<C: Config, F: WebhookMessageFactory> WebhookLayerBuilder<C, F> {
+impl<C: Config, F: WebhookMessageFactory<C>> WebhookLayerBuilder<C, F> {
     pub(crate) fn new(app_name: String, target_filters: EventFilters) -> Self {
         Self {
             factory: Default::default(),
@@ -130,8 +717,40 @@ impl<C: Config, F: WebhookMessageFactory> WebhookLayerBuilder<C, F> {
             message_filters: None,
             event_by_field_filters: None,
             field_exclusion_filters: None,
-            level_filters: None,
+            field_allowlist: None,
+            field_denylist: HashSet::new(),
+            field_renames: HashMap::new(),
+            max_field_length: None,
+            scrubber: None,
+            hashed_fields: HashSet::new(),
+            field_hash_salt: String::new(),
+            priority_fields: Vec::new(),
+            capture_backtraces: false,
+            target_sample_rates: Vec::new(),
+            level_sample_rates: HashMap::new(),
+            rate_limit: None,
+            dedup_window: None,
+            cooldown: None,
+            digest: None,
+            batch: None,
+            fingerprint: None,
+            retry: RetryPolicy::default(),
+            circuit_breaker: None,
+            fallback: None,
+            persistent_queue: None,
+            backpressure: None,
+            prioritize_errors: false,
+            concurrency: 1,
+            level_filter: None,
+            target_directives: None,
+            event_predicate: None,
             config: None,
+            escalations: Vec::new(),
+            sink: None,
+            http_client: None,
+            on_delivery_error: None,
+            on_delivered: None,
+            runtime: runtime::default_runtime(),
         }
     }
 
@@ -155,7 +774,7 @@ impl<C: Config, F: WebhookMessageFactory> WebhookLayerBuilder<C, F> {
         self
     }
 
-    /// Filter fields of events from being sent to Discord.
+    /// Filter fields of events from being sent to the webhook.
     ///
     /// Filter type semantics:
     /// - Positive: Exclude event fields if the field's key MATCHES any provided regular expressions.
@@ -164,29 +783,531 @@ impl<C: Config, F: WebhookMessageFactory> WebhookLayerBuilder<C, F> {
         self
     }
 
-    /// Configure the layer's connection to the Discord Webhook API.
+    /// Only send the given fields of events and their spans. Other fields are dropped before the
+    /// message is created, and are neither rendered nor visible to the message factory.
+    ///
+    /// The event's message is always sent.
+    pub fn field_allowlist<I, S>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.field_allowlist = Some(fields.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Never send the given fields of events and their spans (e.g. tokens or request bodies). They are
+    /// dropped before the message is created, and are neither rendered nor visible to the message
+    /// factory.
+    pub fn field_denylist<I, S>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.field_denylist.extend(fields.into_iter().map(Into::into));
+        self
+    }
+
+    /// Render fields under another name (e.g. `otel.trace_id` as `trace`), mapping each field's name to
+    /// its display name.
+    ///
+    /// Fields are only renamed in the rendered metadata: filters, predicates, and routing still refer
+    /// to them by their own name.
+    pub fn field_renames(mut self, renames: HashMap<String, String>) -> Self {
+        self.field_renames = renames;
+        self
+    }
+
+    /// Truncate rendered field values longer than the given number of characters, ending them with the
+    /// suffix instead (e.g. `max_field_length(500, "…")`), so a single huge value like a SQL query does
+    /// not blow up the message.
+    pub fn max_field_length(mut self, max_length: usize, suffix: impl Into<String>) -> Self {
+        self.max_field_length = Some((max_length, suffix.into()));
+        self
+    }
+
+    /// Scrub secrets from the message and field values of events before they leave the process, e.g.
+    /// `scrubber(Scrubber::with_builtins().pattern(Regex::new("sk_live_[0-9a-zA-Z]+").unwrap()))`.
+    pub fn scrubber(mut self, scrubber: Scrubber) -> Self {
+        self.scrubber = Some(scrubber);
+        self
+    }
+
+    /// Replace the values of the given fields (e.g. user IDs or email addresses) with the hex-encoded
+    /// SHA-256 hash of their value, so messages can still be correlated without identifying anyone.
+    pub fn hash_fields<I, S>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.hashed_fields.extend(fields.into_iter().map(Into::into));
+        self
+    }
+
+    /// Prepend a secret salt to the values of [hashed fields](Self::hash_fields) before hashing them,
+    /// so the hashes cannot be reversed by hashing known values.
+    pub fn field_hash_salt(mut self, salt: impl Into<String>) -> Self {
+        self.field_hash_salt = salt.into();
+        self
+    }
+
+    /// Render the given fields before all other fields, in this order (e.g. `["error", "request_id"]`).
+    /// Other fields are rendered in alphabetical order.
+    ///
+    /// Renamed fields are referred to by the name they are rendered with.
+    pub fn priority_fields<I, S>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.priority_fields = fields.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Capture a backtrace at every ERROR event and include it in the event's message, without the
+    /// frames of the standard library, `tracing`, and this layer.
+    ///
+    /// Capturing a backtrace is expensive, so events of other levels never capture one.
+    pub fn capture_backtraces(mut self, capture_backtraces: bool) -> Self {
+        self.capture_backtraces = capture_backtraces;
+        self
+    }
+
+    /// Send a random sample of one in `one_in` events of a target and its submodules (e.g.
+    /// `sample_target("my_app::http", 100)`), so high-volume events still show up occasionally.
+    ///
+    /// The sample rate of the most specific target applies, and takes precedence over the sample rate of
+    /// the event's level.
+    pub fn sample_target(mut self, target: impl Into<String>, one_in: u32) -> Self {
+        self.target_sample_rates.push((target.into(), one_in));
+        self
+    }
+
+    /// Send a random sample of one in `one_in` events of a level (e.g. `sample_level(Level::INFO, 100)`),
+    /// unless their target has a [sample rate](Self::sample_target).
+    pub fn sample_level(mut self, level: Level, one_in: u32) -> Self {
+        self.level_sample_rates.insert(level, one_in);
+        self
+    }
+
+    /// Deliver at most `max_messages` messages per interval, e.g. to keep an error loop from flooding
+    /// the channel until the webhook is blocked.
+    ///
+    /// Messages beyond the limit are dropped, and counted in a single message summarizing how many
+    /// were suppressed, which is sent once the interval after the first suppressed message is over.
+    pub fn rate_limit(mut self, max_messages: u32, interval: Duration) -> Self {
+        self.rate_limit = Some((max_messages, interval));
+        self
+    }
+
+    /// How often, and how long apart, failed deliveries are attempted before a message is dropped (see
+    /// [`RetryPolicy`]).
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Stop attempting deliveries once `failures` consecutive messages could not be delivered, e.g. while
+    /// the endpoint is down, until the cool-off period is over.
+    ///
+    /// Messages are dropped without any attempt while the breaker is open. Once the cool-off period is
+    /// over, the next message is attempted, and once one is delivered, a [`Notice::Recovered`] reports
+    /// how long the outage lasted and how many messages were not delivered.
+    pub fn circuit_breaker(mut self, failures: u32, cool_off: Duration) -> Self {
+        self.circuit_breaker = Some((failures, cool_off));
+        self
+    }
+
+    /// Write the messages which could not be delivered to a fallback, e.g. `FallbackWriter::stderr()` or
+    /// `FallbackWriter::file("undelivered.jsonl")?`, so alerts are not silently lost during an outage.
+    pub fn fallback(mut self, fallback: FallbackWriter) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// Append every message to a write-ahead log at the given path before it is delivered, so messages
+    /// which were not delivered when the process stopped, e.g. during an outage, are delivered once it
+    /// restarts.
+    ///
+    /// Messages are removed from the log once they are delivered or rejected by the endpoint, and the
+    /// messages left in it are delivered on startup, before any new message. The log holds the webhook
+    /// URLs of the messages, so it is only readable by the current user. Replayed messages are no longer
+    /// part of a thread and have no attachments.
+    pub fn persistent_queue(mut self, path: impl Into<PathBuf>) -> Self {
+        self.persistent_queue = Some(path.into());
+        self
+    }
+
+    /// Queue at most `capacity` events for the worker, blocking the thread which emits an event while the
+    /// queue is full, so events are never dropped because they are emitted faster than they are delivered.
+    ///
+    /// Without backpressure, the queue is unbounded. Blocking requires a thread which does not run the
+    /// worker: on a current-thread tokio runtime, events emitted on the runtime's thread are queued beyond
+    /// the capacity, and so are events emitted by the worker itself, e.g. from `on_delivered` or
+    /// `on_delivery_error`. [`WorkerMetrics::bypassed`](crate::WorkerMetrics::bypassed) counts these events.
+    ///
+    /// This mode only blocks: events are neither dropped nor awaited asynchronously while the queue is
+    /// full, since emitting an event is synchronous.
+    pub fn backpressure(mut self, capacity: usize) -> Self {
+        self.backpressure = Some(capacity);
+        self
+    }
+
+    /// Send queued ERROR events before every other queued message, so errors go out immediately even
+    /// while the worker is working through a backlog of less severe events.
+    ///
+    /// Errors may then be sent before events which occurred earlier. Messages other than ERROR events,
+    /// e.g. notices and flushes, keep their order, and errors never jump ahead of a flush or the shutdown,
+    /// so a flush still waits for every event sent before it, and nothing sent after it.
+    pub fn prioritize_errors(mut self, prioritize_errors: bool) -> Self {
+        self.prioritize_errors = prioritize_errors;
+        self
+    }
+
+    /// Deliver up to `senders` messages concurrently, so a burst of messages is delivered faster than one
+    /// request after another.
+    ///
+    /// Messages posted to the same destination, e.g. a channel, and the messages of a thread are still
+    /// delivered in order, by the same sender. By default, a single sender delivers every message.
+    pub fn concurrency(mut self, senders: usize) -> Self {
+        self.concurrency = senders.max(1);
+        self
+    }
+
+    /// Group consecutive identical events within the given window, e.g. a retry loop logging the same
+    /// error, instead of sending a message for each of them.
+    ///
+    /// The first event of a group is sent immediately. If it repeats within the window, the latest
+    /// occurrence is sent once the window is over or a different event is sent, annotated with the number
+    /// of occurrences (e.g. `Retrying request (×37 in the last 5m)`). Events are identical if they have
+    /// the same [fingerprint](Self::fingerprint).
+    pub fn deduplicate(mut self, window: Duration) -> Self {
+        self.dedup_window = Some(window);
+        self
+    }
+
+    /// Silence an event for the given period after it was sent, so further events with the same
+    /// [fingerprint](Self::fingerprint) are not sent until the period is over, even if other events are
+    /// sent in between.
+    ///
+    /// Once the period is over, the latest silenced occurrence is sent, annotated with the number of
+    /// silenced occurrences (e.g. `Payment failed (×12 more in the last 10m)`).
+    pub fn cooldown(mut self, period: Duration) -> Self {
+        self.cooldown = Some(period);
+        self
+    }
+
+    /// Send events less severe than the given level together as one digest per interval, instead of
+    /// individually, e.g. `digest(Duration::from_secs(900), Level::ERROR)` to send ERROR events
+    /// immediately and a digest of all other events every 15 minutes.
+    ///
+    /// The digest counts the events of each target and level, and includes the messages of the first few
+    /// events. The factory renders it as a [`Notice::Digest`].
+    pub fn digest(mut self, interval: Duration, level: Level) -> Self {
+        self.digest = Some((interval, level));
+        self
+    }
+
+    /// Queue events and send them together, once `max_size` events are queued or the first of them was
+    /// queued for `max_latency`, so a burst of events does not cost one request per event.
+    ///
+    /// The factory combines the events of a batch into as few messages as it can (see
+    /// [`WebhookMessageFactory::create_batch`]).
+    pub fn batch(mut self, max_size: usize, max_latency: Duration) -> Self {
+        self.batch = Some((max_size, max_latency));
+        self
+    }
+
+    /// Identify identical events by their target, line, and the values of the given fields (e.g.
+    /// `["error_code"]`), instead of their message, for events whose messages embed varying values.
+    ///
+    /// This is a shorthand for a [fingerprint](Self::fingerprint), and replaces any fingerprint set
+    /// before.
+    pub fn dedup_key<I, S>(self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let fields: Vec<String> = fields.into_iter().map(Into::into).collect();
+        self.fingerprint(move |inputs| {
+            let mut fingerprint = format!("{}:{}", inputs.target, inputs.source_line);
+            for field in &fields {
+                fingerprint.push('\0');
+                if let Some(value) = inputs.fields.get(field) {
+                    fingerprint.push_str(&value.to_string());
+                }
+            }
+            fingerprint
+        })
+    }
+
+    /// Identify the events which are the same for grouping them with a custom function, e.g. by an
+    /// error code field:
+    ///
+    /// ```ignore
+    /// builder.fingerprint(|inputs| format!("{}:{:?}", inputs.target, inputs.fields.get("error_code")))
+    /// ```
+    ///
+    /// By default, events are identified by their target, line, and message, as in
+    /// [`WebhookMessageInputs::fingerprint`].
+    pub fn fingerprint<P>(mut self, fingerprint: P) -> Self
+    where
+        P: Fn(&WebhookMessageInputs) -> String + Send + Sync + 'static,
+    {
+        self.fingerprint = Some(Arc::new(fingerprint));
+        self
+    }
+
+    /// Configure the layer's connection to the webhook API.
     pub fn config(mut self, config: C) -> Self {
         self.config = Some(config);
         self
     }
 
-    /// Configure which levels of events to send to Discord.
+    /// Render the events with another factory and its configuration, keeping every other option of the
+    /// builder, e.g. to post Microsoft Teams cards instead of Slack messages:
+    ///
+    /// ```ignore
+    /// SlackLayer::builder(app_name, targets).factory::<TeamsLayer, _>(TeamsConfig::new(webhook_url))
+    /// ```
+    pub fn factory<G, D>(self, config: D) -> WebhookLayerBuilder<D, G>
+    where
+        D: Config,
+        G: WebhookMessageFactory<D>,
+    {
+        WebhookLayerBuilder {
+            factory: std::marker::PhantomData,
+            app_name: self.app_name,
+            target_filters: self.target_filters,
+            message_filters: self.message_filters,
+            event_by_field_filters: self.event_by_field_filters,
+            field_exclusion_filters: self.field_exclusion_filters,
+            field_allowlist: self.field_allowlist,
+            field_denylist: self.field_denylist,
+            field_renames: self.field_renames,
+            max_field_length: self.max_field_length,
+            scrubber: self.scrubber,
+            hashed_fields: self.hashed_fields,
+            field_hash_salt: self.field_hash_salt,
+            priority_fields: self.priority_fields,
+            capture_backtraces: self.capture_backtraces,
+            target_sample_rates: self.target_sample_rates,
+            level_sample_rates: self.level_sample_rates,
+            rate_limit: self.rate_limit,
+            dedup_window: self.dedup_window,
+            cooldown: self.cooldown,
+            digest: self.digest,
+            batch: self.batch,
+            fingerprint: self.fingerprint,
+            retry: self.retry,
+            circuit_breaker: self.circuit_breaker,
+            fallback: self.fallback,
+            persistent_queue: self.persistent_queue,
+            backpressure: self.backpressure,
+            prioritize_errors: self.prioritize_errors,
+            concurrency: self.concurrency,
+            level_filter: self.level_filter,
+            target_directives: self.target_directives,
+            event_predicate: self.event_predicate,
+            config: Some(config),
+            escalations: self.escalations,
+            sink: self.sink,
+            http_client: self.http_client,
+            on_delivery_error: self.on_delivery_error,
+            on_delivered: self.on_delivered,
+            runtime: self.runtime,
+        }
+    }
+
+    /// Also send the events of `level` and more severe levels with another factory and its configuration,
+    /// e.g. to trigger PagerDuty alerts for ERROR events besides posting them to Slack:
+    ///
+    /// ```ignore
+    /// SlackLayer::builder(app_name, targets)
+    ///     .escalate::<PagerDutyLayer, _>(Level::ERROR, PagerDutyConfig::new(routing_key))
+    /// ```
+    ///
+    /// Only events passing the layer's filters are escalated. The escalated messages share the worker's
+    /// rate limit and retry policy, and are delivered through the transport of their own configuration,
+    /// unless the builder has a custom [`sink`](Self::sink).
+    pub fn escalate<G, D>(mut self, level: Level, config: D) -> Self
+    where
+        D: Config + Send + Sync + 'static,
+        G: WebhookMessageFactory<D> + 'static,
+    {
+        let config = Arc::new(config);
+        let webhook_urls = config.webhook_urls().into_iter().map(str::to_string).collect();
+        let render: EscalationRenderer = {
+            let config = config.clone();
+            Arc::new(move |inputs| {
+                config
+                    .webhook_urls()
+                    .into_iter()
+                    .flat_map(|webhook_url| {
+                        let inputs = WebhookMessageInputs {
+                            webhook_url: webhook_url.to_string(),
+                            ..inputs.clone()
+                        };
+                        G::create_all(&config, inputs)
+                    })
+                    .collect()
+            })
+        };
+        let sink = Box::new(move |http_client: Option<HttpClient>| {
+            config_sink(&*config, http_client).unwrap_or_else(sinks::default_sink)
+        });
+        self.escalations.push(Escalation {
+            level,
+            render,
+            webhook_urls,
+            sink,
+        });
+        self
+    }
+
+    /// Deliver messages through a custom transport, instead of POSTing them to their webhook URL.
+    ///
+    /// This takes precedence over the transport provided by the layer's configuration, if any.
+    pub fn sink(mut self, sink: impl Sink + 'static) -> Self {
+        self.sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Send every request with the given HTTP client, e.g. one configured centrally with mTLS, a custom
+    /// resolver, or middleware, instead of a client built from the layer's configuration.
+    ///
+    /// The client's own settings apply, so the configuration's proxy, timeouts, and connection pool
+    /// settings are ignored. A custom [`sink`](Self::sink) takes precedence.
+    #[cfg(feature = "tokio")]
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    /// Call `hook` with the error of every message which could not be delivered, e.g. to count delivery
+    /// failures, log them elsewhere, or page someone when Slack is unreachable.
+    ///
+    /// The hook is called once per message, with the error of its last attempt, after its retries were
+    /// exhausted or the endpoint rejected it. It runs on the worker, so it should not block.
+    pub fn on_delivery_error<H>(mut self, hook: H) -> Self
+    where
+        H: Fn(DeliveryError) + Send + Sync + 'static,
+    {
+        self.on_delivery_error = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` with every message which was delivered, and the HTTP status the endpoint responded
+    /// with, e.g. to emit metrics or to assert which messages were sent in integration tests.
+    ///
+    /// The status is `None` for custom sinks which do not deliver messages over HTTP. The hook runs on the
+    /// worker, so it should not block.
+    pub fn on_delivered<H>(mut self, hook: H) -> Self
+    where
+        H: Fn(&dyn WebhookMessage, Option<u16>) + Send + Sync + 'static,
+    {
+        self.on_delivered = Some(Arc::new(hook));
+        self
+    }
+
+    /// Run the background worker on the given async runtime rather than tokio, e.g. `SmolRuntime` with the
+    /// `smol` feature or `AsyncStdRuntime` with the `async-std` feature.
+    ///
+    /// `build` spawns the worker onto this runtime, so it must be called within it. `build_blocking`
+    /// ignores it, since its worker runs on threads of its own.
+    pub fn runtime(mut self, runtime: impl Runtime + 'static) -> Self {
+        self.runtime = Arc::new(runtime);
+        self
+    }
+
+    /// Configure which levels of events to send to the webhook.
+    ///
+    /// The level is parsed the same way as `RUST_LOG` levels (e.g. "warn"). An unrecognized level
+    /// disables sending events entirely.
     pub fn level_filters(mut self, level_filters: String) -> Self {
-        self.level_filters = Some(level_filters);
+        self.level_filter = Some(LevelFilter::from_str(&level_filters).unwrap_or(LevelFilter::OFF));
+        self
+    }
+
+    /// Only send events at the given level or above (e.g. `Level::WARN` for WARN and ERROR events).
+    ///
+    /// Events below this level are discarded before any of their fields are recorded or serialized,
+    /// independently of the level of the subscriber this layer is attached to.
+    pub fn level_filter(mut self, level: Level) -> Self {
+        self.level_filter = Some(LevelFilter::from_level(level));
         self
     }
 
-    /// Create a DiscordLayer and its corresponding background worker to (async) send the messages.
-    pub fn build(self) -> (WebhookLayer<C, F>, BackgroundWorker) {
-        WebhookLayer::new(
-            self.app_name,
-            self.target_filters,
-            self.message_filters,
-            self.event_by_field_filters,
-            self.field_exclusion_filters,
-            self.level_filters,
-            self.config.unwrap_or_else(C::new_from_env),
-        )
+    /// Configure per-target levels of events to send, using the same directive syntax as `RUST_LOG`
+    /// (e.g. `"my_app=warn,my_app::payments=info".parse::<Targets>()`).
+    ///
+    /// Events whose target is not matched by any directive are not sent, unless a default level has
+    /// been set using [`Targets::with_default`].
+    pub fn target_directives(mut self, directives: Targets) -> Self {
+        self.target_directives = Some(directives);
+        self
+    }
+
+    /// Only send events for which the predicate returns true.
+    ///
+    /// The predicate receives the event's metadata and its fields, merged with the fields of the
+    /// event's current span (the event's own fields take precedence). For example, to only send events
+    /// which carry an `alert = true` field:
+    ///
+    /// ```ignore
+    /// builder.event_predicate(|_, fields| fields.get("alert") == Some(&serde_json::Value::Bool(true)))
+    /// ```
+    pub fn event_predicate<P>(mut self, predicate: P) -> Self
+    where
+        P: Fn(&tracing::Metadata<'_>, &FieldMap<'_>) -> bool + Send + Sync + 'static,
+    {
+        self.event_predicate = Some(Box::new(predicate));
+        self
+    }
+
+    /// Create the webhook layer and its corresponding background worker to (async) send the messages.
+    pub fn build(self) -> (WebhookLayer<C, F>, BackgroundWorker)
+    where
+        C: Send + Sync + 'static,
+        F: 'static,
+    {
+        let runtime = self.runtime.clone();
+        WebhookLayer::new(self, |worker| WorkerTask::Async(runtime::spawn(&*runtime, worker)))
+    }
+
+    /// Create the layer and a guard which shuts its background worker down when dropped, waiting up to
+    /// `timeout` for the queued messages to be sent, like `tracing-appender`'s non-blocking writer.
+    ///
+    /// Keep the guard alive until the end of `main`, e.g. `let (layer, _guard) = ...;`.
+    pub fn build_with_guard(self, timeout: Duration) -> (WebhookLayer<C, F>, WorkerGuard)
+    where
+        C: Send + Sync + 'static,
+        F: 'static,
+    {
+        let (layer, worker) = self.build();
+        (layer, WorkerGuard::new(worker, timeout))
+    }
+
+    /// Create the layer and a background worker running on a dedicated thread, for applications which do
+    /// not run an async runtime, e.g. CLI tools and synchronous services.
+    ///
+    /// The worker thread blocks on the worker without starting any runtime: its tasks run on threads of
+    /// their own (see [`ThreadRuntime`]), and messages are sent with the blocking `ureq` client of
+    /// [`BlockingWebhookSink`], unless the builder has a custom [`sink`](Self::sink). The configuration's
+    /// own transport and the builder's [`http_client`](Self::http_client) use reqwest's async client, which
+    /// needs a tokio runtime, so they are not used, and neither is the Slack Web API. Shut the worker down
+    /// with [`BackgroundWorker::shutdown_blocking`] before exiting.
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(mut self) -> (WebhookLayer<C, F>, BackgroundWorker)
+    where
+        C: Send + Sync + 'static,
+        F: 'static,
+    {
+        self.runtime = Arc::new(ThreadRuntime);
+        if self.sink.is_none() {
+            self.sink = Some(Arc::new(BlockingWebhookSink::new()));
+        }
+        WebhookLayer::new(self, WorkerTask::thread)
     }
 }
 
@@ -194,101 +1315,339 @@ impl<S, C, F> Layer<S> for WebhookLayer<C, F>
 where
     S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
     C: Config+ 'static,
-    F: WebhookMessageFactory + 'static,
+    F: WebhookMessageFactory<C> + 'static,
 {
+    /// Evaluate the level and target filters once per callsite, so events from rejected callsites are
+    /// discarded by `on_event` without evaluating any filters or recording any fields.
+    ///
+    /// The layer's own interest applies to the whole subscriber, so it does not disable callsites, which
+    /// would hide their events from every other layer as well. [`WebhookLayer::filtered`] applies the
+    /// filters as a per-layer filter instead, which disables rejected callsites for this layer only, so
+    /// their events never reach `on_event`.
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if metadata.is_event() {
+            if let Ok(mut callsite_filters) = self.callsite_filters.write() {
+                callsite_filters.register(metadata);
+            }
+        }
+        Interest::always()
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = match span.parent() {
+                Some(parent) => FieldStorage::inherit(parent.extensions().get()),
+                None => FieldStorage::default(),
+            };
+            attrs.record(&mut fields);
+            let mut extensions = span.extensions_mut();
+            extensions.insert(fields);
+            extensions.insert(MessageThread::new());
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        // Values recorded after the span was created replace the values of the same fields, including
+        // those inherited from the span's parent.
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<FieldStorage>() {
+                values.record(fields);
+            }
+        }
+    }
+
     fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if !self.switch.is_enabled() || self.muted.load(Ordering::Relaxed) {
+            return;
+        }
+        let callsite = event.metadata().callsite();
+        let rejected = match self.callsite_filters.read() {
+            Ok(callsite_filters) if callsite_filters.disabled.contains(&callsite) => return,
+            Ok(callsite_filters) => callsite_filters.notify.contains(&callsite),
+            Err(_) => false,
+        };
+        if !self.sampled(event.metadata()) {
+            return;
+        }
+
         let current_span = ctx.lookup_current();
-        let mut event_visitor = JsonStorage::default();
+        let mut event_visitor = FieldStorage::default();
         event.record(&mut event_visitor);
 
-        let format = || {
-            const KEYWORDS: [&str; 2] = ["message", "error"];
+        // Events rejected by the level and target filters are only sent if they ask to be.
+        if rejected && !Self::event_flag(&event_visitor, "notify") {
+            return;
+        }
+        // Events may opt out of being sent, e.g. noisy call sites within a module which is sent.
+        if Self::event_flag(&event_visitor, "skip") {
+            return;
+        }
 
+        let format = || {
             let target = event.metadata().target();
-            self.target_filters.process(target)?;
 
-            // Extract the "message" field, if provided. Fallback to the target, if missing.
-            let message = event_visitor
-                .values()
-                .get("message")
-                .and_then(|v| match v {
-                    Value::String(s) => Some(s.as_str()),
-                    _ => None,
-                })
-                .or_else(|| {
-                    event_visitor.values().get("error").and_then(|v| match v {
-                        Value::String(s) => Some(s.as_str()),
-                        _ => None,
-                    })
+            let mut fields = FieldMap::new();
+            if let Some(span) = &current_span {
+                if let Some(visitor) = span.extensions().get::<FieldStorage>() {
+                    fields.extend(visitor.values().iter().map(|(&key, value)| (key, value.clone())));
+                }
+            }
+            fields.extend(event_visitor.values().iter().map(|(&key, value)| (key, value.clone())));
+            if let Some(predicate) = &self.event_predicate {
+                if !predicate(event.metadata(), &fields) {
+                    return Err(FilterError::PositiveFilterFailed);
+                }
+            }
+
+            // Reserved fields instruct the factory how to handle this event, and are never rendered.
+            let is_reserved = |key: &str| F::RESERVED_FIELD_PREFIX.is_some_and(|prefix| key.starts_with(prefix));
+            let mut reserved_fields = HashMap::new();
+            if let Some(prefix) = F::RESERVED_FIELD_PREFIX {
+                fields.retain(|&key, value| match key.strip_prefix(prefix) {
+                    Some(name) => {
+                        reserved_fields.insert(name.to_string(), value.take());
+                        false
+                    }
+                    None => true,
+                });
+            }
+
+            fields.retain(|&key, _| self.field_allowed(key));
+            for (key, value) in fields.iter_mut() {
+                if let Cow::Owned(anonymized) = self.anonymize(key, value) {
+                    *value = anonymized;
+                }
+            }
+
+            // The fields as they are rendered, for templates, which must not reveal excluded fields.
+            let rendered_fields = fields
+                .iter()
+                .filter(|(&key, _)| self.field_exclusion_filters.process(key).is_ok())
+                .map(|(&key, value)| {
+                    let value = self.truncate(Cow::Borrowed(value)).into_owned();
+                    (self.display_name(key).to_string(), value)
                 })
+                .collect();
+
+            // Extract the "message" field, if provided. Fallback to the error, if missing.
+            let message_key = ["message", "error"]
+                .iter()
+                .copied()
+                .find(|&key| event_visitor.values().get(key).is_some_and(Value::is_string));
+            let message = message_key
+                .and_then(|key| event_visitor.values()[key].as_str())
                 .unwrap_or("No message");
 
             self.message_filters.process(message)?;
-            if let Some(level_filters) = &self.level_filter {
-                let message_level = {
-                    LevelFilter::from_str(event.metadata().level().as_str())
-                        .map_err(|e| FilterError::IoError(Box::new(e)))?
-                };
-                let level_threshold =
-                    LevelFilter::from_str(level_filters).map_err(|e| FilterError::IoError(Box::new(e)))?;
-                if message_level > level_threshold {
-                    return Err(FilterError::PositiveFilterFailed);
-                }
-            }
 
             let mut metadata_buffer = Vec::new();
             let mut serializer = serde_json::Serializer::new(&mut metadata_buffer);
             let mut map_serializer = serializer.serialize_map(None)?;
             // Add all the other fields associated with the event, expect the message we
-            // already used.
+            // already used. An error is only listed along with the message it accompanies.
             for (key, value) in event_visitor
                 .values()
                 .iter()
-                .filter(|(&key, _)| !KEYWORDS.contains(&key))
+                .filter(|(&key, _)| key != "message" && Some(key) != message_key)
+                .filter(|(&key, _)| !is_reserved(key) && self.field_allowed(key))
                 .filter(|(&key, _)| self.field_exclusion_filters.process(key).is_ok())
             {
                 self.event_by_field_filters.process(key)?;
-                map_serializer.serialize_entry(key, value)?;
+                map_serializer.serialize_entry(self.display_name(key), &self.display_value(key, value))?;
             }
             // Add all the fields from the current span, if we have one.
             if let Some(span) = &current_span {
                 let extensions = span.extensions();
-                if let Some(visitor) = extensions.get::<JsonStorage>() {
-                    for (key, value) in visitor.values() {
-                        map_serializer.serialize_entry(key, value)?;
+                if let Some(visitor) = extensions.get::<FieldStorage>() {
+                    let span_fields = visitor.values().iter();
+                    for (key, value) in span_fields.filter(|(&key, _)| !is_reserved(key) && self.field_allowed(key)) {
+                        map_serializer.serialize_entry(self.display_name(key), &self.display_value(key, value))?;
                     }
                 }
             }
             map_serializer.end()?;
 
+            // The names of all spans the event is in, from the outermost to the event's current span.
+            // Their fields are already included in the current span's, as each span inherits the fields
+            // of its parent.
             let span = match &current_span {
-                Some(span) => span.metadata().name(),
-                None => "",
+                Some(span) => {
+                    let names: Vec<&str> = span.scope().from_root().map(|span| span.metadata().name()).collect();
+                    names.join(" > ")
+                }
+                None => String::new(),
             };
 
             let metadata = {
-                let data: HashMap<String, Value> = serde_json::from_slice(metadata_buffer.as_slice()).unwrap();
-                serde_json::to_string_pretty(&data).unwrap()
+                // Priority fields are listed first, and all other fields by name, so they are easy to
+                // find in the rendered metadata.
+                let mut data: BTreeMap<String, Value> = serde_json::from_slice(metadata_buffer.as_slice()).unwrap();
+                let mut ordered: Vec<(String, Value)> = self
+                    .priority_fields
+                    .iter()
+                    .filter_map(|field| data.remove_entry(field))
+                    .collect();
+                ordered.extend(data);
+                serde_json::to_string_pretty(&OrderedFields(ordered)).unwrap()
             };
 
-            Ok(F::create(WebhookMessageInputs {
+            let thread = current_span
+                .as_ref()
+                .and_then(|span| span.extensions().get::<MessageThread>().cloned());
+
+            Ok(WebhookMessageInputs {
                 app_name: self.app_name.clone(),
-                message: message.to_string(),
+                message: match &self.scrubber {
+                    Some(scrubber) => scrubber.scrub(message).into_owned(),
+                    None => message.to_string(),
+                },
                 event_level: *event.metadata().level(),
                 source_file: event.metadata().file().unwrap_or("Unknown").to_string(),
                 source_line: event.metadata().line().unwrap_or(0),
                 target: target.to_string(),
-                span: span.to_string(),
+                span,
                 metadata,
+                fields: fields
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value))
+                    .collect(),
+                rendered_fields,
+                reserved_fields,
                 webhook_url: self.config.webhook_url().to_string(),
-            }))
+                thread,
+                span_trace: event_visitor.span_trace().map(|span_trace| match &self.scrubber {
+                    Some(scrubber) => scrubber.scrub(span_trace).into_owned(),
+                    None => span_trace.to_string(),
+                }),
+                backtrace: (target == PANIC_TARGET
+                    || self.capture_backtraces && *event.metadata().level() == Level::ERROR)
+                    .then(|| trim_backtrace(&Backtrace::force_capture())),
+            })
         };
 
         let result: Result<_, FilterError> = format();
-        if let Ok(formatted) = result {
-            if let Err(e) = self.sender.send(WorkerMessage::Data(Box::new(formatted))) {
-                println!("failed to send discord payload to given channel, err = {}", e)
+        if let Ok(inputs) = result {
+            if let Some(thread) = &inputs.thread {
+                thread.record_message(&inputs.event_level);
+            }
+            let bypassed = self
+                .backpressure
+                .as_ref()
+                .is_some_and(|backpressure| !backpressure.acquire());
+            let message = if bypassed {
+                WorkerMessage::Bypassed(Box::new(inputs))
+            } else {
+                WorkerMessage::Event(Box::new(inputs))
+            };
+            self.queue.push();
+            if let Err(e) = self.sender.try_send(message) {
+                self.queue.pop();
+                println!("ERROR: failed to send webhook payload to the worker, err = {}", e)
+            };
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let thread = match span.extensions().get::<MessageThread>() {
+            Some(thread) if thread.messages() > 0 => thread.clone(),
+            _ => return,
+        };
+        let summary = SpanSummary {
+            app_name: self.app_name.clone(),
+            span: span.metadata().name().to_string(),
+            duration: thread.elapsed(),
+            failed: thread.failed(),
+            thread,
+            webhook_url: self.config.webhook_url().to_string(),
+        };
+        if let Some(message) = F::close(&self.config, summary) {
+            if let Err(e) = self.sender.try_send(WorkerMessage::Data(message)) {
+                println!("failed to send span summary to given channel, err = {}", e)
             };
         }
     }
 }
+
+/// The name of the host the application runs on.
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// The most frames of a backtrace included in a message.
+const MAX_BACKTRACE_FRAMES: usize = 20;
+
+/// The prefixes of the symbols of frames which are omitted from backtraces, as they belong to the
+/// machinery capturing the backtrace rather than the application.
+const OMITTED_BACKTRACE_FRAMES: [&str; 8] = [
+    "std::",
+    "core::",
+    "alloc::",
+    "tracing::",
+    "tracing_core::",
+    "tracing_subscriber::",
+    "tracing_layer_core::",
+    "__rust",
+];
+
+/// Render a backtrace without the frames of the standard library, `tracing`, and this layer, and with
+/// at most [`MAX_BACKTRACE_FRAMES`] frames.
+fn trim_backtrace(backtrace: &Backtrace) -> String {
+    let rendered = backtrace.to_string();
+    // Every frame starts with its number and symbol, followed by lines naming its source location.
+    let mut frames: Vec<Vec<&str>> = Vec::new();
+    for line in rendered.lines() {
+        let is_frame = line
+            .trim_start()
+            .split_once(": ")
+            .is_some_and(|(n, _)| n.parse::<usize>().is_ok());
+        match frames.last_mut() {
+            Some(frame) if !is_frame => frame.push(line),
+            _ => frames.push(vec![line]),
+        }
+    }
+    let frames: Vec<String> = frames
+        .into_iter()
+        .filter(|frame| {
+            // Trait implementations are named like `<tracing_subscriber::Layered<..> as ..>::on_event`.
+            let symbol = frame[0].trim_start().split_once(": ").map_or("", |(_, symbol)| symbol);
+            // Frames without debug info to resolve their symbol are of no help either.
+            symbol != "<unknown>"
+                && !OMITTED_BACKTRACE_FRAMES
+                    .iter()
+                    .any(|prefix| symbol.trim_start_matches('<').starts_with(prefix))
+        })
+        .map(|frame| frame.join("\n"))
+        .collect();
+    let omitted = frames.len().saturating_sub(MAX_BACKTRACE_FRAMES);
+    let mut trimmed = frames
+        .into_iter()
+        .take(MAX_BACKTRACE_FRAMES)
+        .collect::<Vec<_>>()
+        .join("\n");
+    if omitted > 0 {
+        trimmed.push_str(&format!("\n      ... {} more frames", omitted));
+    }
+    trimmed
+}
+
+/// Fields serialized as a JSON object in the order they are listed.
+struct OrderedFields(Vec<(String, Value)>);
+
+impl serde::Serialize for OrderedFields {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (field, value) in &self.0 {
+            map.serialize_entry(field, value)?;
+        }
+        map.end()
+    }
+}
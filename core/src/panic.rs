@@ -0,0 +1,56 @@
+use std::any::Any;
+use std::time::Duration;
+
+use crate::BackgroundWorker;
+
+/// The target of the events reporting panics, which bypass the layer's target filters.
+pub const PANIC_TARGET: &str = "panic";
+
+/// How long a panicking thread waits for its report to be delivered.
+const PANIC_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Report panics as ERROR events, and wait for the worker to deliver them before the panic proceeds.
+///
+/// The event's message is the panic's message, and it records the thread and source location of the
+/// panic. A backtrace of the panicking thread is always captured. The previously installed panic hook
+/// (by default, the one printing the panic to stderr) runs first.
+///
+/// The panicking thread waits at most five seconds for the report to be delivered. If the panic occurs
+/// on the only thread of a single-threaded runtime, the worker cannot make progress while the thread
+/// waits, so the report is only delivered if the runtime outlives the panic.
+pub fn install_panic_hook(worker: BackgroundWorker) {
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        previous_hook(info);
+
+        let message = panic_message(info.payload());
+        let location = info
+            .location()
+            .map(|location| location.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let current_thread = std::thread::current();
+        tracing::error!(
+            target: PANIC_TARGET,
+            thread = current_thread.name().unwrap_or("<unnamed>"),
+            location = location.as_str(),
+            "panicked: {}",
+            message,
+        );
+        // The worker recovers from its own panics, and cannot deliver the report while it waits for it.
+        if crate::worker::recovering_panics() {
+            return;
+        }
+        if !worker.flush_blocking(PANIC_FLUSH_TIMEOUT) {
+            println!("ERROR: timed out delivering panic report to webhook message worker");
+        }
+    }));
+}
+
+/// The message of a panic, if it is a string.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message.as_str(),
+        _ => "Box<dyn Any>",
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::WebhookMessage;
+
+/// How many records the log may hold before it is rewritten with only its pending messages.
+const COMPACT_AFTER: usize = 1000;
+
+/// A message read back from the log, which is delivered with its serialized payload.
+///
+/// Only the payload and webhook URL are stored, so a replayed message is not part of a thread and has
+/// no attachment.
+#[derive(Debug, Clone)]
+pub(crate) struct StoredMessage {
+    webhook_url: String,
+    payload: String,
+}
+
+impl StoredMessage {
+    pub(crate) fn new(webhook_url: String, payload: String) -> Self {
+        Self { webhook_url, payload }
+    }
+}
+
+impl WebhookMessage for StoredMessage {
+    fn webhook_url(&self) -> &str {
+        &self.webhook_url
+    }
+
+    fn serialize(&self) -> String {
+        self.payload.clone()
+    }
+}
+
+/// A record of the log: a message which is about to be delivered, or the acknowledgement that the
+/// message with the given ID is settled.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Message {
+        id: u64,
+        webhook_url: String,
+        payload: String,
+    },
+    Ack {
+        ack: u64,
+    },
+}
+
+/// A write-ahead log of the messages which are being delivered, so messages which were not delivered
+/// before the process stopped are delivered once it restarts.
+///
+/// Every message is appended to the log before it is delivered, and acknowledged once it was delivered
+/// or rejected by the endpoint. Messages which could not be delivered stay in the log until the next
+/// start of the worker, which delivers them before any new message.
+pub(crate) struct MessageLog {
+    path: PathBuf,
+    file: File,
+    next_id: u64,
+    /// The messages which are not acknowledged yet, by their ID.
+    pending: BTreeMap<u64, StoredMessage>,
+    /// The number of records in the file.
+    records: usize,
+}
+
+impl MessageLog {
+    /// Open the log at the given path, creating it if it does not exist. Returns the log and the messages
+    /// which were not acknowledged before, which should be delivered again.
+    pub(crate) fn open(path: &Path) -> std::io::Result<(Self, Vec<(u64, StoredMessage)>)> {
+        let mut pending = BTreeMap::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                // A record which was only partially written when the process stopped is skipped.
+                match serde_json::from_str(&line?) {
+                    Ok(Record::Message {
+                        id,
+                        webhook_url,
+                        payload,
+                    }) => {
+                        pending.insert(id, StoredMessage::new(webhook_url, payload));
+                    }
+                    Ok(Record::Ack { ack }) => {
+                        pending.remove(&ack);
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+        // The live log is only replaced once the compacted log was written in full, so the pending
+        // messages survive a failure to compact it.
+        let log = Self {
+            path: path.to_path_buf(),
+            file: Self::rewrite(path, &pending)?,
+            next_id: pending.keys().next_back().map_or(0, |id| id + 1),
+            records: pending.len(),
+            pending,
+        };
+        let replay = log.pending.iter().map(|(id, message)| (*id, message.clone())).collect();
+        Ok((log, replay))
+    }
+
+    /// Append a message to the log before it is delivered, returning its ID.
+    pub(crate) fn append(&mut self, message: &dyn WebhookMessage) -> Option<u64> {
+        let id = self.next_id;
+        let message = StoredMessage::new(message.webhook_url().to_string(), message.serialize());
+        let record = Record::Message {
+            id,
+            webhook_url: message.webhook_url.clone(),
+            payload: message.payload.clone(),
+        };
+        if let Err(e) = self.write(&record) {
+            println!(
+                "ERROR: failed to append webhook message to {}: {}",
+                self.path.display(),
+                e
+            );
+            return None;
+        }
+        self.next_id += 1;
+        self.pending.insert(id, message);
+        Some(id)
+    }
+
+    /// Acknowledge that a message was delivered, or will never be.
+    pub(crate) fn ack(&mut self, id: u64) {
+        if self.pending.remove(&id).is_none() {
+            return;
+        }
+        let written = if self.pending.is_empty() || self.records >= COMPACT_AFTER {
+            self.compact()
+        } else {
+            self.write(&Record::Ack { ack: id })
+        };
+        if let Err(e) = written {
+            println!(
+                "ERROR: failed to acknowledge webhook message in {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    fn write(&mut self, record: &Record) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Rewrite the log with only its pending messages.
+    fn compact(&mut self) -> std::io::Result<()> {
+        self.file = Self::rewrite(&self.path, &self.pending)?;
+        self.records = self.pending.len();
+        Ok(())
+    }
+
+    /// Write the pending messages to a temporary file, which then replaces the log at the given path, and
+    /// open the new log for appending.
+    fn rewrite(path: &Path, pending: &BTreeMap<u64, StoredMessage>) -> std::io::Result<File> {
+        let temporary = path.with_extension("tmp");
+        let mut file = Self::create(&temporary)?;
+        for (id, message) in pending {
+            let record = Record::Message {
+                id: *id,
+                webhook_url: message.webhook_url.clone(),
+                payload: message.payload.clone(),
+            };
+            let mut line = serde_json::to_string(&record)?;
+            line.push('\n');
+            file.write_all(line.as_bytes())?;
+        }
+        file.sync_all()?;
+        std::fs::rename(&temporary, path)?;
+        OpenOptions::new().append(true).open(path)
+    }
+
+    /// Create or truncate a file which only the current user may read, as webhook URLs embed a secret.
+    fn create(path: &Path) -> std::io::Result<File> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(path)
+    }
+}
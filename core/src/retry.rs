@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// How often, and how long apart, the background worker attempts to deliver a message whose delivery
+/// failed with a [retryable](crate::DeliveryError::is_retryable) error.
+///
+/// The delay before each retry doubles, starting at the base delay, up to the maximum delay. With
+/// jitter, each delay is chosen at random between half and all of it, so many processes failing at
+/// once do not retry in lockstep. A message is dropped once every attempt failed.
+///
+/// The default policy makes 10 attempts, starting with a delay of 100 milliseconds, with at most 30
+/// seconds between attempts, and with jitter.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+}
+
+impl RetryPolicy {
+    /// A policy making at most the given number of attempts, including the first one.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            ..Self::default()
+        }
+    }
+
+    /// A policy making a single attempt, never retrying a failed delivery.
+    pub fn never() -> Self {
+        Self::new(1)
+    }
+
+    /// The delay before the first retry.
+    pub fn base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// The longest delay between two attempts.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Whether delays are randomized.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// The delay after the given failed attempt, counted from 1, before the next attempt.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        if self.jitter && !delay.is_zero() {
+            rand::thread_rng().gen_range(delay / 2..=delay)
+        } else {
+            delay
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
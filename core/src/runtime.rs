@@ -0,0 +1,283 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A future spawned or returned by a [`Runtime`].
+pub type RuntimeFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// The async runtime a background worker runs on: it spawns the worker's tasks and drives its timers.
+///
+/// The worker runs on tokio by default. With the `async-std` or `smol` feature, it can run on those
+/// runtimes instead, by passing [`AsyncStdRuntime`] or [`SmolRuntime`] to the layer builder's `runtime`.
+/// Without the `tokio` feature, it runs on [`ThreadRuntime`] unless given another runtime.
+///
+/// The worker only depends on its runtime through this trait: its channels are those of `async-channel`
+/// and `futures-channel`, and its timers are the runtime's sleeps. The transport is the runtime's concern,
+/// though: reqwest's client, used by [`WebhookSink`](crate::WebhookSink) with the `tokio` feature, needs
+/// tokio's I/O driver, which the async-std runtime provides through async-std's `tokio1` integration, and
+/// the smol runtime by entering a tokio runtime of its own. Without the `tokio` feature, messages are sent
+/// with `ureq` instead, which needs no I/O driver.
+pub trait Runtime: Send + Sync {
+    /// Spawn a task running the future to completion in the background.
+    fn spawn(&self, future: RuntimeFuture);
+
+    /// A future completing once the duration elapsed.
+    fn sleep(&self, duration: Duration) -> RuntimeFuture;
+}
+
+/// Runs the worker on the tokio runtime the layer is built on.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioRuntime;
+
+#[cfg(feature = "tokio")]
+impl Runtime for TokioRuntime {
+    fn spawn(&self, future: RuntimeFuture) {
+        tokio::spawn(future);
+    }
+
+    fn sleep(&self, duration: Duration) -> RuntimeFuture {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// The runtime the worker runs on unless the builder is given another one: tokio with the `tokio` feature,
+/// and threads of its own otherwise.
+pub(crate) fn default_runtime() -> Arc<dyn Runtime> {
+    #[cfg(feature = "tokio")]
+    return Arc::new(TokioRuntime);
+    #[cfg(not(feature = "tokio"))]
+    return Arc::new(ThreadRuntime);
+}
+
+/// Runs the worker on async-std's global executor.
+#[cfg(feature = "async-std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncStdRuntime;
+
+#[cfg(feature = "async-std")]
+impl Runtime for AsyncStdRuntime {
+    fn spawn(&self, future: RuntimeFuture) {
+        async_std::task::spawn(future);
+    }
+
+    fn sleep(&self, duration: Duration) -> RuntimeFuture {
+        Box::pin(async_std::task::sleep(duration))
+    }
+}
+
+/// Runs the worker on smol's global executor.
+#[cfg(feature = "smol")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmolRuntime;
+
+#[cfg(feature = "smol")]
+impl Runtime for SmolRuntime {
+    fn spawn(&self, future: RuntimeFuture) {
+        #[cfg(feature = "tokio")]
+        let future = tokio_context::within(future);
+        smol::spawn(future).detach();
+    }
+
+    fn sleep(&self, duration: Duration) -> RuntimeFuture {
+        Box::pin(async move {
+            smol::Timer::after(duration).await;
+        })
+    }
+}
+
+/// The tokio runtime whose I/O driver the tasks of the smol runtime use, so reqwest's client, which
+/// registers its connections with tokio's driver, works within them.
+#[cfg(all(feature = "smol", feature = "tokio"))]
+mod tokio_context {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::OnceLock;
+    use std::task::{Context, Poll};
+
+    use tokio::runtime::Handle;
+
+    use super::RuntimeFuture;
+
+    /// The handle of a current-thread tokio runtime, whose driver runs on a thread of its own.
+    fn handle() -> &'static Handle {
+        static HANDLE: OnceLock<Handle> = OnceLock::new();
+        HANDLE.get_or_init(|| {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("failed to start the tokio runtime of the webhook message worker");
+            let handle = runtime.handle().clone();
+            std::thread::Builder::new()
+                .name("webhook-worker-tokio".to_string())
+                .spawn(move || runtime.block_on(std::future::pending::<()>()))
+                .expect("failed to spawn the tokio runtime thread of the webhook message worker");
+            handle
+        })
+    }
+
+    /// Poll the future within the context of the tokio runtime.
+    pub(super) fn within(future: RuntimeFuture) -> impl Future<Output = ()> + Send {
+        Within(future)
+    }
+
+    struct Within(RuntimeFuture);
+
+    impl Future for Within {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let _entered = handle().enter();
+            self.0.as_mut().poll(cx)
+        }
+    }
+}
+
+/// Runs the worker's tasks on threads of their own, each blocking on its task, so the worker needs no async
+/// runtime at all. The runtime of [`build_blocking`](crate::layer::WebhookLayerBuilder::build_blocking), and
+/// the default runtime without the `tokio` feature.
+///
+/// Its timers are driven by a single thread shared by every worker, which wakes each task once its sleep
+/// elapsed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadRuntime;
+
+impl Runtime for ThreadRuntime {
+    fn spawn(&self, future: RuntimeFuture) {
+        let spawned = std::thread::Builder::new()
+            .name("webhook-worker-task".to_string())
+            .spawn(move || futures_lite::future::block_on(future));
+        if let Err(e) = spawned {
+            println!("ERROR: failed to spawn a task of the webhook message worker: {}", e);
+        }
+    }
+
+    fn sleep(&self, duration: Duration) -> RuntimeFuture {
+        Box::pin(timer::Sleep::new(duration))
+    }
+}
+
+/// The timer thread of [`ThreadRuntime`].
+mod timer {
+    use std::collections::BTreeMap;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Condvar, Mutex, MutexGuard, Once, OnceLock};
+    use std::task::{Context, Poll, Waker};
+    use std::time::{Duration, Instant};
+
+    /// The tasks waiting for their sleep to elapse, by deadline, and the signal that an earlier deadline was
+    /// added.
+    #[derive(Default)]
+    struct Timers {
+        wakers: Mutex<BTreeMap<(Instant, u64), Waker>>,
+        changed: Condvar,
+        next_id: AtomicU64,
+    }
+
+    impl Timers {
+        fn get() -> &'static Timers {
+            static TIMERS: OnceLock<Timers> = OnceLock::new();
+            static STARTED: Once = Once::new();
+            let timers = TIMERS.get_or_init(Timers::default);
+            STARTED.call_once(|| {
+                std::thread::Builder::new()
+                    .name("webhook-worker-timer".to_string())
+                    .spawn(move || timers.run())
+                    .expect("failed to spawn the webhook message worker timer thread");
+            });
+            timers
+        }
+
+        fn wakers(&self) -> MutexGuard<'_, BTreeMap<(Instant, u64), Waker>> {
+            self.wakers.lock().unwrap_or_else(|e| e.into_inner())
+        }
+
+        /// Wake the tasks whose deadline passed, then wait for the next deadline, or for an earlier one to be
+        /// added.
+        fn run(&self) {
+            let mut wakers = self.wakers();
+            loop {
+                let now = Instant::now();
+                while let Some(entry) = wakers.first_entry() {
+                    if entry.key().0 > now {
+                        break;
+                    }
+                    entry.remove().wake();
+                }
+                wakers = match wakers.keys().next() {
+                    Some(&(deadline, _)) => {
+                        let timeout = deadline.saturating_duration_since(now);
+                        self.changed
+                            .wait_timeout(wakers, timeout)
+                            .unwrap_or_else(|e| e.into_inner())
+                            .0
+                    }
+                    None => self.changed.wait(wakers).unwrap_or_else(|e| e.into_inner()),
+                };
+            }
+        }
+    }
+
+    /// A future completing once its deadline passed, which is woken by the timer thread.
+    pub(super) struct Sleep {
+        deadline: Instant,
+        id: u64,
+        registered: bool,
+    }
+
+    impl Sleep {
+        pub(super) fn new(duration: Duration) -> Self {
+            Self {
+                deadline: Instant::now() + duration,
+                id: Timers::get().next_id.fetch_add(1, Ordering::Relaxed),
+                registered: false,
+            }
+        }
+    }
+
+    impl Future for Sleep {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if Instant::now() >= self.deadline {
+                return Poll::Ready(());
+            }
+            let timers = Timers::get();
+            let key = (self.deadline, self.id);
+            let mut wakers = timers.wakers();
+            let earliest = wakers.keys().next().is_none_or(|first| key < *first);
+            wakers.insert(key, cx.waker().clone());
+            self.registered = true;
+            if earliest {
+                timers.changed.notify_one();
+            }
+            Poll::Pending
+        }
+    }
+
+    impl Drop for Sleep {
+        fn drop(&mut self) {
+            // A sleep dropped before its deadline, e.g. a timer the worker raced against a message, no longer
+            // needs to be woken.
+            if self.registered {
+                Timers::get().wakers().remove(&(self.deadline, self.id));
+            }
+        }
+    }
+}
+
+/// Spawn a task on the runtime, returning the signal that it completed.
+pub(crate) fn spawn(runtime: &dyn Runtime, future: impl Future<Output = ()> + Send + 'static) -> Completion {
+    let (completed, completion) = futures_channel::oneshot::channel();
+    runtime.spawn(Box::pin(async move {
+        future.await;
+        let _ = completed.send(());
+    }));
+    completion
+}
+
+/// Resolves once a spawned task completed, or panicked.
+pub(crate) type Completion = futures_channel::oneshot::Receiver<()>;
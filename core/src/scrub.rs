@@ -0,0 +1,62 @@
+use std::borrow::Cow;
+
+use regex::Regex;
+use serde_json::Value;
+
+/// The text replacing every match of a [`Scrubber`]'s patterns.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Scrubs secrets from the messages and field values of events before they are sent, replacing every
+/// match of its regular expressions with `[REDACTED]`.
+#[derive(Debug, Clone, Default)]
+pub struct Scrubber {
+    patterns: Vec<Regex>,
+}
+
+impl Scrubber {
+    /// Create a scrubber without any patterns.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a scrubber with the built-in patterns for AWS access keys, AWS secret access keys,
+    /// bearer tokens, and email addresses.
+    pub fn with_builtins() -> Self {
+        let builtins = [
+            r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b",
+            r"(?i)aws_secret_access_key\s*[=:]\s*[A-Za-z0-9/+=]{40}",
+            r"(?i)\bbearer\s+[A-Za-z0-9\-._~+/]+=*",
+            r"[A-Za-z0-9._%+\-]+@[A-Za-z0-9.\-]+\.[A-Za-z]{2,}",
+        ];
+        Self {
+            patterns: builtins.iter().map(|pattern| Regex::new(pattern).unwrap()).collect(),
+        }
+    }
+
+    /// Also scrub matches of the given regular expression.
+    pub fn pattern(mut self, pattern: Regex) -> Self {
+        self.patterns.push(pattern);
+        self
+    }
+
+    /// Replace every match of the scrubber's patterns in the text.
+    pub fn scrub<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for pattern in &self.patterns {
+            if let Cow::Owned(scrubbed) = pattern.replace_all(&text, REDACTED) {
+                text = Cow::Owned(scrubbed);
+            }
+        }
+        text
+    }
+
+    /// Replace every match of the scrubber's patterns in the strings of a JSON value.
+    pub fn scrub_value(&self, value: &Value) -> Value {
+        match value {
+            Value::String(s) => Value::String(self.scrub(s).into_owned()),
+            Value::Array(values) => Value::Array(values.iter().map(|value| self.scrub_value(value)).collect()),
+            Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), self.scrub_value(v))).collect()),
+            value => value.clone(),
+        }
+    }
+}
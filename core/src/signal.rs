@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use crate::{BackgroundWorker, DeliveryReport};
+
+/// Wait until the process is asked to terminate, by `SIGTERM` (e.g. by Kubernetes when a pod is
+/// stopped) or `SIGINT` (ctrl-c), and then shut the worker down, waiting at most `timeout` for the
+/// queued messages to be sent.
+///
+/// The returned future is meant to be the application's own shutdown signal, e.g. the future passed
+/// to a server's graceful shutdown, so the application only proceeds with its shutdown once the errors
+/// which preceded the termination were delivered. Events emitted after the worker shut down are lost.
+pub async fn drain_on_signal(worker: BackgroundWorker, timeout: Duration) -> DeliveryReport {
+    terminated().await;
+    worker.shutdown_timeout(timeout).await
+}
+
+/// Wait for `SIGTERM` or `SIGINT`.
+async fn terminated() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(e) => println!("ERROR: failed to listen for SIGTERM, only listening for ctrl-c: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        println!("ERROR: failed to listen for ctrl-c: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
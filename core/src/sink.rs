@@ -0,0 +1,265 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use debug_print::debug_println;
+
+use crate::WebhookMessage;
+
+/// The future returned by a [`Sink`] while it delivers a message.
+pub type DeliveryFuture<'a> = Pin<Box<dyn Future<Output = Result<Delivered, DeliveryError>> + Send + 'a>>;
+
+/// A message which a [`Sink`] delivered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Delivered {
+    /// The HTTP status the endpoint responded with, if the message was delivered over HTTP.
+    pub status: Option<u16>,
+}
+
+impl Delivered {
+    /// A message delivered over HTTP, which the endpoint responded to with the given status.
+    pub fn with_status(status: u16) -> Self {
+        Self { status: Some(status) }
+    }
+}
+
+/// A transport delivering the messages produced by a layer.
+///
+/// The background worker hands every message to its sink, and retries failed deliveries which are
+/// [retryable](DeliveryError::is_retryable). A sink only needs to make a single delivery attempt.
+pub trait Sink: Send + Sync {
+    fn deliver<'a>(&'a self, message: &'a dyn WebhookMessage) -> DeliveryFuture<'a>;
+}
+
+/// The default sink, which POSTs the serialized message as JSON to the message's webhook URL.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Debug, Default)]
+pub struct WebhookSink {
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "tokio")]
+impl WebhookSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A sink sending requests with the given client, e.g. one configured with a proxy.
+    pub fn with_client(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Sink for WebhookSink {
+    fn deliver<'a>(&'a self, message: &'a dyn WebhookMessage) -> DeliveryFuture<'a> {
+        Box::pin(async move {
+            let payload_json = message.serialize();
+            debug_println!("sending webhook message: {}", &payload_json);
+            let res = self
+                .client
+                .post(message.webhook_url())
+                .header("Content-Type", "application/json")
+                .body(payload_json)
+                .send()
+                .await
+                .map_err(|e| DeliveryError::Request(e.without_url()))?;
+            debug_println!("webhook message sent: {:?}", &res);
+            let status = res.status();
+            if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                return Err(DeliveryError::RateLimited {
+                    retry_after: retry_after(res.headers()),
+                });
+            }
+            let res_text = res.text().await.unwrap_or_default();
+            debug_println!("webhook message response: {}", res_text);
+            if !status.is_success() {
+                return Err(DeliveryError::Status {
+                    status: status.as_u16(),
+                    body: res_text,
+                });
+            }
+            Ok(Delivered::with_status(status.as_u16()))
+        })
+    }
+}
+
+/// A sink POSTing the serialized message as JSON to the message's webhook URL with the blocking `ureq`
+/// client, so delivering messages needs no async runtime: the default sink of
+/// [`build_blocking`](crate::layer::WebhookLayerBuilder::build_blocking), and the default sink without the
+/// `tokio` feature.
+///
+/// Each request blocks a thread of the `blocking` crate's pool rather than the task delivering the message,
+/// so this sink works on every runtime, and within none.
+#[cfg(feature = "ureq")]
+#[derive(Clone, Debug)]
+pub struct BlockingWebhookSink {
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "ureq")]
+impl BlockingWebhookSink {
+    pub fn new() -> Self {
+        Self::with_agent(ureq::Agent::new())
+    }
+
+    /// A sink sending requests with the given agent, e.g. one configured with a proxy or timeouts.
+    pub fn with_agent(agent: ureq::Agent) -> Self {
+        Self { agent }
+    }
+
+    /// POST the payload to the webhook URL, blocking the current thread.
+    fn post(agent: &ureq::Agent, webhook_url: &str, payload_json: &str) -> Result<Delivered, DeliveryError> {
+        let res = agent
+            .post(webhook_url)
+            .set("Content-Type", "application/json")
+            .send_string(payload_json);
+        let res = match res {
+            Ok(res) => res,
+            Err(ureq::Error::Status(429, res)) => {
+                let retry_after = res
+                    .header("Retry-After")
+                    .and_then(|seconds| seconds.trim().parse().ok());
+                return Err(DeliveryError::RateLimited {
+                    retry_after: retry_after.map(Duration::from_secs),
+                });
+            }
+            Err(ureq::Error::Status(status, res)) => {
+                return Err(DeliveryError::Status {
+                    status,
+                    body: res.into_string().unwrap_or_default(),
+                });
+            }
+            Err(ureq::Error::Transport(e)) => {
+                // The error's own rendering includes the webhook URL, which carries its secret.
+                let reason = match e.message() {
+                    Some(message) => format!("request failed: {}: {}", e.kind(), message),
+                    None => format!("request failed: {}", e.kind()),
+                };
+                return Err(DeliveryError::Other(reason.into()));
+            }
+        };
+        let status = res.status();
+        let res_text = res.into_string().unwrap_or_default();
+        debug_println!("webhook message response: {}", res_text);
+        Ok(Delivered::with_status(status))
+    }
+}
+
+#[cfg(feature = "ureq")]
+impl Default for BlockingWebhookSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "ureq")]
+impl Sink for BlockingWebhookSink {
+    fn deliver<'a>(&'a self, message: &'a dyn WebhookMessage) -> DeliveryFuture<'a> {
+        let payload_json = message.serialize();
+        debug_println!("sending webhook message: {}", &payload_json);
+        let agent = self.agent.clone();
+        let webhook_url = message.webhook_url().to_string();
+        Box::pin(blocking::unblock(move || {
+            Self::post(&agent, &webhook_url, &payload_json)
+        }))
+    }
+}
+
+/// The sink delivering messages unless the builder or the layer's configuration provides one:
+/// [`WebhookSink`] with the `tokio` feature, and [`BlockingWebhookSink`] without it.
+pub(crate) fn default_sink() -> Arc<dyn Sink> {
+    #[cfg(feature = "tokio")]
+    return Arc::new(WebhookSink::new());
+    #[cfg(not(feature = "tokio"))]
+    return Arc::new(BlockingWebhookSink::new());
+}
+
+/// Delivers the messages sent to some webhook URLs through their own sinks, e.g. escalated alerts through
+/// the transport of their configuration, and every other message through the layer's sink.
+pub(crate) struct RoutingSink {
+    pub(crate) default: Arc<dyn Sink>,
+    pub(crate) routes: Vec<(Vec<String>, Arc<dyn Sink>)>,
+}
+
+impl Sink for RoutingSink {
+    fn deliver<'a>(&'a self, message: &'a dyn WebhookMessage) -> DeliveryFuture<'a> {
+        let sink = self
+            .routes
+            .iter()
+            .find(|(webhook_urls, _)| webhook_urls.iter().any(|url| url == message.webhook_url()))
+            .map_or(&self.default, |(_, sink)| sink);
+        sink.deliver(message)
+    }
+}
+
+/// The delay requested by the `Retry-After` header of a response, if it gives one in seconds.
+#[cfg(feature = "tokio")]
+pub fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let seconds = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    seconds.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// The reason a message could not be delivered.
+#[derive(Debug)]
+pub enum DeliveryError {
+    /// The request could not be sent, or no response was received.
+    #[cfg(feature = "tokio")]
+    Request(reqwest::Error),
+    /// The endpoint responded with an unsuccessful status code.
+    Status { status: u16, body: String },
+    /// The endpoint is rate limiting requests (`429 Too Many Requests`), and may ask to wait before the
+    /// next request.
+    RateLimited { retry_after: Option<Duration> },
+    /// The endpoint accepted the request, but rejected the message (e.g. an API error response).
+    Rejected(String),
+    /// A custom sink failed to deliver the message.
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl DeliveryError {
+    /// Whether delivering the same message again may succeed.
+    ///
+    /// Network failures, rate limiting, and server errors are retryable; other unsuccessful statuses
+    /// (e.g. an invalid payload or a revoked webhook) and rejected messages are not.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            #[cfg(feature = "tokio")]
+            DeliveryError::Request(_) => true,
+            DeliveryError::RateLimited { .. } | DeliveryError::Other(_) => true,
+            DeliveryError::Status { status, .. } => *status == 429 || *status >= 500,
+            DeliveryError::Rejected(_) => false,
+        }
+    }
+}
+
+impl Display for DeliveryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "tokio")]
+            DeliveryError::Request(e) => write!(f, "request failed: {}", e),
+            DeliveryError::Status { status, body } => write!(f, "unsuccessful response status {}: {}", status, body),
+            DeliveryError::RateLimited {
+                retry_after: Some(retry_after),
+            } => {
+                write!(f, "rate limited, retry after {}s", retry_after.as_secs())
+            }
+            DeliveryError::RateLimited { retry_after: None } => write!(f, "rate limited"),
+            DeliveryError::Rejected(reason) => write!(f, "message rejected: {}", reason),
+            DeliveryError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DeliveryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "tokio")]
+            DeliveryError::Request(e) => Some(e),
+            DeliveryError::Status { .. } | DeliveryError::RateLimited { .. } | DeliveryError::Rejected(_) => None,
+            DeliveryError::Other(e) => Some(e.as_ref()),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Debug;
+
+use serde_json::Value;
+use tracing::field::{Field, Visit};
+
+/// The fields recorded on an event or a span, as JSON values.
+///
+/// Spans store their fields in their extensions. A span's fields include those of its parent, unless the
+/// span records a field of the same name. The parent's fields are copied when the span is created, so
+/// values the parent records later only appear in the events of the parent itself and of spans created
+/// afterwards.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FieldStorage {
+    values: HashMap<&'static str, Value>,
+    /// The rendered span trace carried by the first recorded error which captured one.
+    span_trace: Option<String>,
+}
+
+impl FieldStorage {
+    /// Fields inheriting the fields of a parent span, if there is one.
+    pub(crate) fn inherit(parent: Option<&FieldStorage>) -> Self {
+        parent.cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn values(&self) -> &HashMap<&'static str, Value> {
+        &self.values
+    }
+
+    pub(crate) fn span_trace(&self) -> Option<&str> {
+        self.span_trace.as_deref()
+    }
+
+    fn insert(&mut self, field: &Field, value: Value) {
+        match field.name() {
+            // Fields describing the metadata of `log` records are already part of the event's metadata.
+            name if name.starts_with("log.") => {}
+            // Fields named by raw identifiers may keep their `r#` prefix, depending on the version of `tracing`.
+            name if name.starts_with("r#") => {
+                self.values.insert(&name[2..], value);
+            }
+            name => {
+                self.values.insert(name, value);
+            }
+        }
+    }
+}
+
+impl Visit for FieldStorage {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        // JSON has no representation of NaN and infinite numbers.
+        let value = serde_json::Number::from_f64(value).map_or_else(|| Value::String(value.to_string()), Value::Number);
+        self.insert(field, value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_i128(&mut self, field: &Field, value: i128) {
+        // Numbers beyond 64 bits are recorded as strings, so they do not lose precision.
+        let value = i64::try_from(value).map_or_else(|_| Value::String(value.to_string()), Value::from);
+        self.insert(field, value);
+    }
+
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        let value = u64::try_from(value).map_or_else(|_| Value::String(value.to_string()), Value::from);
+        self.insert(field, value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_bytes(&mut self, field: &Field, value: &[u8]) {
+        let hex: String = value.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.insert(field, Value::String(hex));
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        // The top-level error rarely explains a failure on its own, so its whole source chain is kept.
+        let mut rendered = value.to_string();
+        let mut source = value.source();
+        while let Some(error) = source {
+            rendered.push_str("\ncaused by: ");
+            rendered.push_str(&error.to_string());
+            source = error.source();
+        }
+        self.insert(field, Value::String(rendered));
+
+        #[cfg(feature = "span-trace")]
+        if self.span_trace.is_none() {
+            use tracing_error::{ExtractSpanTrace, SpanTraceStatus};
+
+            let mut error = Some(value);
+            while let Some(e) = error {
+                if let Some(span_trace) = e.span_trace().filter(|t| t.status() == SpanTraceStatus::CAPTURED) {
+                    self.span_trace = Some(span_trace.to_string());
+                    break;
+                }
+                error = e.source();
+            }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.insert(field, Value::String(format!("{:?}", value)));
+    }
+
+    #[cfg(all(tracing_unstable, feature = "valuable"))]
+    fn record_value(&mut self, field: &Field, value: valuable::Value<'_>) {
+        // Structured values are kept as nested JSON, unless they cannot be serialized.
+        let json = serde_json::to_value(valuable_serde::Serializable::new(value))
+            .unwrap_or_else(|_| Value::String(format!("{:?}", value)));
+        self.insert(field, json);
+    }
+}
@@ -1,37 +1,221 @@
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fmt::Debug;
-use std::sync::Arc;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use tokio::task::JoinHandle;
 use debug_print::debug_println;
-use tokio::sync::Mutex;
+use futures_lite::future::race;
+use serde_json::Value;
+use tracing::Level;
 
-use crate::{ChannelReceiver, ChannelSender, WebhookMessage};
+use crate::panic::panic_message;
+use crate::queue::MessageLog;
+use crate::runtime::{self, Completion, Runtime};
+use crate::{
+    format_interval, ChannelReceiver, ChannelSender, DeliveryError, DigestCount, FallbackWriter, Notice, RetryPolicy,
+    Sink, WebhookMessage, WebhookMessageInputs,
+};
 
-/// Maximum number of retries for failed requests
-const MAX_RETRIES: usize = 10;
+/// How many times delivering a message may be paused for as long as a rate limited endpoint asks.
+const MAX_RATE_LIMIT_PAUSES: u32 = 10;
+
+/// The longest pause requested by a rate limited endpoint which is honored.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// The number of messages of digested events included in a digest.
+const DIGEST_SAMPLES: usize = 3;
 
 /// This worker manages a background async task that schedules the network requests to send traces
-/// to the Discord on the running tokio runtime.
+/// to the webhook on the layer's runtime.
 ///
 /// Ensure to invoke `.startup()` before, and `.teardown()` after, your application code runs. This
 /// is required to ensure proper initialization and shutdown.
 ///
-/// `tracing-layer-discord` synchronously generates payloads to send to the Discord API using the
+/// The layer synchronously generates the inputs of the payloads to send to the webhook using the
 /// tracing events from the global subscriber. However, all network requests are offloaded onto
 /// an unbuffered channel and processed by a provided future acting as an asynchronous worker.
 #[derive(Clone)]
 pub struct BackgroundWorker {
     pub(crate) sender: ChannelSender,
-    pub(crate) handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    pub(crate) handle: Arc<Mutex<Option<WorkerTask>>>,
+    pub(crate) stats: Arc<DeliveryStats>,
+    pub(crate) queue: Arc<QueueGauge>,
+    /// The runtime the worker runs on, whose timer bounds `shutdown_timeout`.
+    pub(crate) runtime: Arc<dyn Runtime>,
+    /// Whether the layer drops events, shared with the layer.
+    pub(crate) muted: Arc<AtomicBool>,
+}
+
+/// The number of messages a worker delivered and dropped since the previous report, as returned by
+/// [`BackgroundWorker::flush`] and [`BackgroundWorker::shutdown_timeout`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeliveryReport {
+    /// The messages which were delivered.
+    pub delivered: usize,
+    /// The messages which were not delivered: rejected by the endpoint, failed after every retry,
+    /// dropped by the circuit breaker, or suppressed by the rate limit.
+    pub dropped: usize,
+    /// Whether the worker handled every message queued before the report, rather than stopping or
+    /// timing out first.
+    pub complete: bool,
+}
+
+/// The number of messages a worker handled since it started, as returned by [`BackgroundWorker::metrics`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WorkerMetrics {
+    /// The messages queued for delivery.
+    pub enqueued: usize,
+    /// The messages which were delivered.
+    pub delivered: usize,
+    /// The delivery attempts which failed and were attempted again.
+    pub retried: usize,
+    /// The responses of rate limited endpoints, and the messages suppressed by the rate limit.
+    pub rate_limited: usize,
+    /// The messages which were not delivered, as counted by [`DeliveryReport::dropped`].
+    pub dropped: usize,
+    /// The events currently queued for the worker, which it did not get to yet.
+    pub queued: usize,
+    /// How long the oldest event queued for the worker has been waiting, if any is queued. A growing
+    /// age means delivery is falling behind the events emitted.
+    pub oldest_queued: Option<Duration>,
+    /// The events queued beyond the capacity of the [backpressure](crate::layer::WebhookLayerBuilder::backpressure)
+    /// queue, because the thread emitting them could not block while it was full.
+    pub bypassed: usize,
+}
+
+/// The names of the metrics emitted through the `metrics` crate facade, which mirror [`WorkerMetrics`].
+#[cfg(feature = "metrics")]
+pub(crate) const ENQUEUED_METRIC: &str = "tracing_layer_messages_enqueued_total";
+#[cfg(feature = "metrics")]
+pub(crate) const DELIVERED_METRIC: &str = "tracing_layer_messages_delivered_total";
+#[cfg(feature = "metrics")]
+pub(crate) const RETRIED_METRIC: &str = "tracing_layer_delivery_retries_total";
+#[cfg(feature = "metrics")]
+pub(crate) const RATE_LIMITED_METRIC: &str = "tracing_layer_rate_limited_total";
+#[cfg(feature = "metrics")]
+pub(crate) const DROPPED_METRIC: &str = "tracing_layer_messages_dropped_total";
+#[cfg(feature = "metrics")]
+pub(crate) const QUEUED_METRIC: &str = "tracing_layer_queued_events";
+#[cfg(feature = "metrics")]
+pub(crate) const OLDEST_QUEUED_METRIC: &str = "tracing_layer_oldest_queued_event_seconds";
+#[cfg(feature = "metrics")]
+pub(crate) const BYPASSED_METRIC: &str = "tracing_layer_backpressure_bypassed_total";
+
+/// The number of messages delivered and dropped by a worker since the previous report, and its metrics.
+#[derive(Debug, Default)]
+pub(crate) struct DeliveryStats {
+    delivered: AtomicUsize,
+    dropped: AtomicUsize,
+    total_enqueued: AtomicUsize,
+    total_delivered: AtomicUsize,
+    total_retried: AtomicUsize,
+    total_rate_limited: AtomicUsize,
+    total_dropped: AtomicUsize,
+    total_bypassed: AtomicUsize,
+}
+
+impl DeliveryStats {
+    pub(crate) fn record(&self, delivered: bool) {
+        let (counter, total) = if delivered {
+            (&self.delivered, &self.total_delivered)
+        } else {
+            (&self.dropped, &self.total_dropped)
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        total.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!(if delivered { DELIVERED_METRIC } else { DROPPED_METRIC }).increment(1);
+    }
+
+    pub(crate) fn record_enqueued(&self) {
+        self.total_enqueued.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!(ENQUEUED_METRIC).increment(1);
+    }
+
+    pub(crate) fn record_retried(&self) {
+        self.total_retried.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!(RETRIED_METRIC).increment(1);
+    }
+
+    pub(crate) fn record_rate_limited(&self) {
+        self.total_rate_limited.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!(RATE_LIMITED_METRIC).increment(1);
+    }
+
+    pub(crate) fn record_bypassed(&self) {
+        self.total_bypassed.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!(BYPASSED_METRIC).increment(1);
+    }
+
+    pub(crate) fn metrics(&self) -> WorkerMetrics {
+        WorkerMetrics {
+            enqueued: self.total_enqueued.load(Ordering::Relaxed),
+            delivered: self.total_delivered.load(Ordering::Relaxed),
+            retried: self.total_retried.load(Ordering::Relaxed),
+            rate_limited: self.total_rate_limited.load(Ordering::Relaxed),
+            dropped: self.total_dropped.load(Ordering::Relaxed),
+            queued: 0,
+            oldest_queued: None,
+            bypassed: self.total_bypassed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The report of the messages handled since the previous report.
+    pub(crate) fn take(&self, complete: bool) -> DeliveryReport {
+        DeliveryReport {
+            delivered: self.delivered.swap(0, Ordering::Relaxed),
+            dropped: self.dropped.swap(0, Ordering::Relaxed),
+            complete,
+        }
+    }
+}
+
+/// The task running a worker's receive-send loop.
+pub(crate) enum WorkerTask {
+    /// A task spawned on the application's runtime, and the signal that it completed.
+    Async(Completion),
+    /// A dedicated thread blocking on the worker, and the signal that the worker stopped.
+    #[cfg(feature = "blocking")]
+    Thread(std::thread::JoinHandle<()>, futures_channel::oneshot::Receiver<()>),
+}
+
+impl WorkerTask {
+    /// Spawn the worker on a dedicated thread, so applications without an async runtime can use the layer.
+    /// The thread blocks on the worker itself, without starting any runtime.
+    #[cfg(feature = "blocking")]
+    pub(crate) fn thread(worker: impl Future<Output = ()> + Send + 'static) -> Self {
+        let (stopped, on_stop) = futures_channel::oneshot::channel();
+        let thread = std::thread::Builder::new()
+            .name("webhook-worker".to_string())
+            .spawn(move || {
+                futures_lite::future::block_on(worker);
+                let _ = stopped.send(());
+            })
+            .expect("failed to spawn the webhook message worker thread");
+        WorkerTask::Thread(thread, on_stop)
+    }
 }
 
 impl BackgroundWorker {
     /// Initiate the worker's shutdown sequence.
     ///
-    /// Without invoking`.teardown()`, your application may exit before all Discord messages can be
+    /// Without invoking`.teardown()`, your application may exit before all webhook messages can be
     /// sent.
     pub async fn shutdown(self) {
-        match self.sender.send(WorkerMessage::Shutdown) {
+        match self.sender.try_send(WorkerMessage::Shutdown) {
             Ok(..) => {
                 debug_println!("webhook message worker shutdown");
             }
@@ -39,63 +223,1524 @@ impl BackgroundWorker {
                 println!("ERROR: failed to send shutdown message to webhook message worker: {}", e);
             }
         }
-        let mut guard = self.handle.lock().await;
-        if let Some(handle) = guard.take() {
-            let _ = handle.await;
-        } else {
-            println!("ERROR: async task handle to webhook message worker has been already dropped");
+        let task = lock(&self.handle).take();
+        match task {
+            Some(WorkerTask::Async(completion)) => {
+                let _ = completion.await;
+            }
+            // The worker's thread is awaited through a channel rather than `spawn_blocking`, so this does not
+            // block a thread of the caller's runtime.
+            #[cfg(feature = "blocking")]
+            Some(WorkerTask::Thread(thread, on_stop)) => {
+                let _ = on_stop.await;
+                let _ = thread.join();
+            }
+            None => println!("ERROR: async task handle to webhook message worker has been already dropped"),
+        }
+    }
+
+    /// Initiate the worker's shutdown sequence, and block the current thread until the worker sent every
+    /// queued message, for applications without a tokio runtime (see
+    /// [`build_blocking`](crate::layer::WebhookLayerBuilder::build_blocking)).
+    ///
+    #[cfg(feature = "blocking")]
+    pub fn shutdown_blocking(self) {
+        if let Err(e) = self.sender.try_send(WorkerMessage::Shutdown) {
+            println!(
+                "ERROR: failed to send shutdown message to webhook message worker: {}",
+                e
+            );
+        }
+        let task = lock(&self.handle).take();
+        match task {
+            Some(WorkerTask::Thread(thread, _)) => {
+                let _ = thread.join();
+            }
+            Some(WorkerTask::Async(_)) => {
+                println!("ERROR: the webhook message worker runs on an async runtime, use `shutdown().await`")
+            }
+            None => println!("ERROR: the webhook message worker has been already shut down"),
+        }
+    }
+
+    /// A snapshot of the number of messages the worker handled since it started, e.g. to monitor the
+    /// delivery of messages itself.
+    pub fn metrics(&self) -> WorkerMetrics {
+        WorkerMetrics {
+            queued: self.queue.depth(),
+            oldest_queued: self.queue.oldest_age(),
+            ..self.stats.metrics()
+        }
+    }
+
+    /// Wait until the worker sent every message queued before, e.g. before a step which may terminate the
+    /// process. Returns how many messages were delivered and dropped since the previous report.
+    pub async fn flush(&self) -> DeliveryReport {
+        let (ack, flushed) = futures_channel::oneshot::channel();
+        if self.sender.try_send(WorkerMessage::FlushAsync(ack)).is_err() {
+            return self.stats.take(false);
+        }
+        let complete = flushed.await.is_ok();
+        self.stats.take(complete)
+    }
+
+    /// Shut the worker down, waiting at most `timeout` for it to send the queued messages. Returns how
+    /// many messages were delivered and dropped since the previous report, and whether the worker sent
+    /// every queued message in time.
+    ///
+    /// Messages the worker did not get to in time are lost once the process exits.
+    pub async fn shutdown_timeout(self, timeout: Duration) -> DeliveryReport {
+        if self.sender.try_send(WorkerMessage::Shutdown).is_err() {
+            return self.stats.take(false);
         }
+        let task = lock(&self.handle).take();
+        let complete = match task {
+            Some(WorkerTask::Async(completion)) => within(&*self.runtime, timeout, completion).await,
+            #[cfg(feature = "blocking")]
+            Some(WorkerTask::Thread(_, on_stop)) => within(&*self.runtime, timeout, on_stop).await,
+            None => false,
+        };
+        self.stats.take(complete)
     }
 
+    /// Post a message marking a point in time, e.g. `worker.post_marker("Deployed v2.3.1")`, so the
+    /// messages around it can be correlated with a deployment or another change.
+    ///
+    /// The marker is queued after all messages of events which occurred before.
+    pub fn post_marker(&self, text: impl Into<String>) {
+        self.send_notice(Notice::Marker(text.into()));
+    }
+
+    /// Send a one-off message through the worker, bypassing the tracing pipeline, so it is delivered
+    /// with the same retries as the messages of events.
+    pub fn send_message(&self, text: impl Into<String>) {
+        self.send_notice(Notice::Message {
+            text: text.into(),
+            blocks: None,
+        });
+    }
+
+    /// Send a one-off message laid out with blocks (e.g. Slack's Block Kit), with a text fallback for
+    /// notifications and for platforms without blocks.
+    pub fn send_blocks(&self, text: impl Into<String>, blocks: Value) {
+        self.send_notice(Notice::Message {
+            text: text.into(),
+            blocks: Some(blocks),
+        });
+    }
+
+    /// Mute the messages of events, e.g. during a maintenance window, until the worker is unmuted.
+    ///
+    /// Events which occur while the worker is muted are dropped by the layer before their fields are
+    /// recorded, so they are neither rendered nor queued. Events queued before, markers, one-off messages,
+    /// and other notices are still sent.
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    fn send_notice(&self, notice: Notice) {
+        if let Err(e) = self.sender.try_send(WorkerMessage::Notice(notice)) {
+            println!("ERROR: failed to send message to webhook message worker: {}", e);
+        }
+    }
+
+    /// Block the current thread until the worker has handled every message sent before, or until the
+    /// timeout elapses. Returns whether the worker caught up in time.
+    pub(crate) fn flush_blocking(&self, timeout: Duration) -> bool {
+        let (ack, flushed) = std::sync::mpsc::sync_channel(1);
+        if self.sender.try_send(WorkerMessage::Flush(ack)).is_err() {
+            return false;
+        }
+        flushed.recv_timeout(timeout).is_ok()
+    }
 }
 
+/// Shuts the worker down when dropped, e.g. at the end of `main`, waiting until every queued message was
+/// sent or the timeout elapsed, so messages emitted just before the application exits are not lost.
+///
+/// Dropping the guard blocks the current thread. On a current-thread tokio runtime, the worker cannot
+/// run while the runtime's thread is blocked, so the guard only waits for the timeout there; use a
+/// multi-threaded runtime or `build_blocking` instead.
+#[must_use = "the worker is shut down as soon as the guard is dropped"]
+pub struct WorkerGuard {
+    worker: BackgroundWorker,
+    timeout: Duration,
+}
+
+impl WorkerGuard {
+    pub(crate) fn new(worker: BackgroundWorker, timeout: Duration) -> Self {
+        Self { worker, timeout }
+    }
+
+    /// The guarded worker, e.g. to post markers or to mute it.
+    pub fn worker(&self) -> &BackgroundWorker {
+        &self.worker
+    }
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        if self.worker.sender.try_send(WorkerMessage::Shutdown).is_err() {
+            return;
+        }
+        // The worker never handles the flush queued after the shutdown: it drops the flush once it stopped,
+        // which disconnects the acknowledgement.
+        let (ack, stopped) = std::sync::mpsc::sync_channel(1);
+        if self.worker.sender.try_send(WorkerMessage::Flush(ack)).is_err() {
+            return;
+        }
+        if let Err(std::sync::mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(self.timeout) {
+            println!(
+                "ERROR: webhook message worker did not send its queued messages within {}",
+                format_interval(self.timeout)
+            );
+        }
+    }
+}
+
+/// Renders the messages of events sent together to every destination.
+pub(crate) type EventRenderer = Arc<dyn Fn(Vec<WebhookMessageInputs>) -> Vec<Box<dyn WebhookMessage>> + Send + Sync>;
+
+/// Called with the error of a message which could not be delivered.
+pub(crate) type DeliveryErrorHook = Arc<dyn Fn(DeliveryError) + Send + Sync>;
+
+/// Called with a message which was delivered, and the HTTP status of its response if it was sent over HTTP.
+pub(crate) type DeliveredHook = Arc<dyn Fn(&dyn WebhookMessage, Option<u16>) + Send + Sync>;
+
+/// Identifies events which are the same for grouping them, e.g. an event repeated in a loop.
+pub(crate) type Fingerprint = Arc<dyn Fn(&WebhookMessageInputs) -> String + Send + Sync>;
+
+/// Renders the messages announcing a notice to every destination.
+pub(crate) type NoticeRenderer = Arc<dyn Fn(&Notice) -> Vec<Box<dyn WebhookMessage>> + Send + Sync>;
+
 /// A command sent to a worker containing a new message that should be sent to a webhook endpoint.
 #[derive(Debug)]
 pub enum WorkerMessage {
     Data(Box<dyn WebhookMessage>),
+    /// Send an event, using the layer's factory to produce its messages.
+    Event(Box<WebhookMessageInputs>),
+    /// Send an event queued beyond the capacity of the backpressure queue, which holds no slot of it.
+    Bypassed(Box<WebhookMessageInputs>),
+    /// Announce a notice, using the layer's factory to produce its messages.
+    Notice(Notice),
+    /// Acknowledge once every message sent before has been handled.
+    Flush(std::sync::mpsc::SyncSender<()>),
+    /// Acknowledge once every message sent before has been handled, to a task awaiting the flush.
+    FlushAsync(futures_channel::oneshot::Sender<()>),
     Shutdown,
 }
 
+/// Options of the worker, set by the layer's builder and configuration.
+pub(crate) struct WorkerOptions {
+    pub(crate) heartbeat_interval: Option<Duration>,
+    /// The most messages delivered per interval.
+    pub(crate) rate_limit: Option<(u32, Duration)>,
+    /// The window within which consecutive identical events are grouped.
+    pub(crate) dedup_window: Option<Duration>,
+    /// How long further occurrences of a sent event are silenced.
+    pub(crate) cooldown: Option<Duration>,
+    /// How often events less severe than the level are sent as a digest, instead of individually.
+    pub(crate) digest: Option<(Duration, Level)>,
+    /// The most events sent together, and how long events are queued at most to be sent together.
+    pub(crate) batch: Option<(usize, Duration)>,
+    pub(crate) fingerprint: Fingerprint,
+    pub(crate) retry: RetryPolicy,
+    /// How many consecutive failed deliveries open the circuit breaker, and for how long.
+    pub(crate) circuit_breaker: Option<(u32, Duration)>,
+    /// Where messages which could not be delivered are written.
+    pub(crate) fallback: Option<FallbackWriter>,
+    /// The write-ahead log of the messages which are being delivered.
+    pub(crate) persistent_queue: Option<PathBuf>,
+    /// Bounds the number of queued events, blocking the layer while the queue is full.
+    pub(crate) backpressure: Option<Arc<Backpressure>>,
+    /// The times the events queued for the worker were emitted, shared with the layer.
+    pub(crate) queue: Arc<QueueGauge>,
+    /// Whether queued ERROR events are handled before every other queued message.
+    pub(crate) prioritize_errors: bool,
+    /// How many messages may be delivered concurrently.
+    pub(crate) concurrency: usize,
+    /// The number of messages delivered and dropped, shared with the worker's handle.
+    pub(crate) stats: Arc<DeliveryStats>,
+    /// Called with the error of every message which could not be delivered.
+    pub(crate) on_delivery_error: Option<DeliveryErrorHook>,
+    /// Called with every message which was delivered, and the HTTP status of its response.
+    pub(crate) on_delivered: Option<DeliveredHook>,
+    /// The runtime spawning the worker's sender tasks and driving its timers.
+    pub(crate) runtime: Arc<dyn Runtime>,
+}
+
 /// Provides a background worker task that sends the messages generated by the
 /// layer.
-pub(crate) async fn worker(mut rx: ChannelReceiver) {
-    let client = reqwest::Client::new();
-    while let Some(message) = rx.recv().await {
+pub(crate) async fn worker(
+    rx: ChannelReceiver,
+    sink: Arc<dyn Sink>,
+    events: EventRenderer,
+    notices: NoticeRenderer,
+    options: WorkerOptions,
+) {
+    OnWorker(Box::pin(run(rx, sink, events, notices, options))).await
+}
+
+async fn run(
+    mut rx: ChannelReceiver,
+    sink: Arc<dyn Sink>,
+    events: EventRenderer,
+    notices: NoticeRenderer,
+    options: WorkerOptions,
+) {
+    let runtime = &*options.runtime;
+    let events: EventRenderer = Arc::new(move |batch| render("events", || events(batch)));
+    let notices: NoticeRenderer = Arc::new(move |notice| render("a notice", || notices(notice)));
+    let mut delivery = Delivery {
+        sink,
+        retry: options.retry,
+        breaker: options
+            .circuit_breaker
+            .map(|(threshold, cool_off)| Arc::new(std::sync::Mutex::new(CircuitBreaker::new(threshold, cool_off)))),
+        fallback: options
+            .fallback
+            .map(|fallback| Arc::new(std::sync::Mutex::new(fallback))),
+        log: None,
+        stats: options.stats.clone(),
+        on_error: options.on_delivery_error.clone(),
+        on_delivered: options.on_delivered.clone(),
+        runtime: options.runtime.clone(),
+    };
+    if let Some(path) = &options.persistent_queue {
+        match MessageLog::open(path) {
+            Ok((log, replay)) => {
+                delivery.log = Some(Arc::new(std::sync::Mutex::new(log)));
+                // Messages which were not delivered before the process stopped are delivered first.
+                for (id, message) in replay {
+                    delivery.stats.record_enqueued();
+                    delivery.send(&message, Some(id)).await;
+                }
+            }
+            Err(e) => println!("ERROR: failed to open the persistent queue {}: {}", path.display(), e),
+        }
+    }
+    let dispatcher = Dispatcher::new(delivery, options.concurrency);
+    let mut heartbeat = options.heartbeat_interval.map(Ticker::new);
+    let mut rate_limiter = options
+        .rate_limit
+        .map(|(max_messages, interval)| RateLimiter::new(max_messages, interval));
+    let mut digest = options.digest.map(|(interval, level)| Digest::new(interval, level));
+    let mut digest_timer = options.digest.map(|(interval, _)| Ticker::new(interval));
+    let mut batcher = options
+        .batch
+        .map(|(max_size, max_latency)| Batcher::new(max_size, max_latency));
+    let fingerprint = options.fingerprint;
+    let mut grouping = Grouping {
+        deduplicator: options
+            .dedup_window
+            .map(|window| Deduplicator::new(window, fingerprint.clone())),
+        cooldown: options
+            .cooldown
+            .map(|period| Cooldown::new(period, fingerprint.clone())),
+    };
+    let mut lanes = Lanes::new(options.prioritize_errors);
+    loop {
+        if let Some(recovered) = dispatcher.delivery.take_recovery() {
+            for payload in notices(&recovered) {
+                dispatcher.send(payload, false).await;
+            }
+        }
+        let summary_due = rate_limiter.as_ref().and_then(|rate_limiter| rate_limiter.summary_due);
+        let grouping_due = grouping.due();
+        let batch_due = batcher.as_ref().and_then(Batcher::due);
+        // Wait for the next message, or the first timer which is due, whichever comes first. Either one is
+        // picked at random when several are ready, so a backlog of messages does not delay the timers.
+        let wake = {
+            let message = async { Wake::Message(lanes.next(&mut rx).await) };
+            let heartbeat_tick = async {
+                tick(runtime, heartbeat.as_mut()).await;
+                Wake::Heartbeat
+            };
+            let summary = async {
+                sleep_until(runtime, summary_due).await;
+                Wake::Summary
+            };
+            let digest_tick = async {
+                tick(runtime, digest_timer.as_mut()).await;
+                Wake::Digest
+            };
+            let grouping = async {
+                sleep_until(runtime, grouping_due).await;
+                Wake::Grouping
+            };
+            let batch = async {
+                sleep_until(runtime, batch_due).await;
+                Wake::Batch
+            };
+            let timers = race(race(heartbeat_tick, summary), race(digest_tick, race(grouping, batch)));
+            race(message, timers).await
+        };
+        let message = match wake {
+            Wake::Message(message) => message,
+            Wake::Heartbeat => {
+                let interval = heartbeat.as_ref().map(|heartbeat| heartbeat.period).unwrap_or_default();
+                let messages = dispatcher.take_delivered();
+                for payload in notices(&Notice::Heartbeat { messages, interval }) {
+                    dispatcher.send(payload, false).await;
+                }
+                continue;
+            }
+            Wake::Summary => {
+                if let Some(summary) = rate_limiter.as_mut().and_then(RateLimiter::take_summary) {
+                    for payload in notices(&summary) {
+                        dispatcher.send(payload, false).await;
+                    }
+                }
+                continue;
+            }
+            Wake::Digest => {
+                if let Some(digest) = digest.as_mut().and_then(Digest::take_notice) {
+                    for payload in notices(&digest) {
+                        dispatcher.send(payload, false).await;
+                    }
+                }
+                continue;
+            }
+            Wake::Grouping => {
+                let summaries = grouping.take_due(Instant::now());
+                for payload in batched(batcher.as_mut(), summaries, Instant::now())
+                    .into_iter()
+                    .flat_map(&*events)
+                {
+                    dispatcher.send_limited(rate_limiter.as_mut(), payload).await;
+                }
+                continue;
+            }
+            Wake::Batch => {
+                let batch = batcher.as_mut().map(Batcher::take).unwrap_or_default();
+                for payload in events(batch) {
+                    dispatcher.send_limited(rate_limiter.as_mut(), payload).await;
+                }
+                continue;
+            }
+        };
+        let message = match message {
+            Some(message) => message,
+            None => break,
+        };
+        let bypassed = matches!(message, WorkerMessage::Bypassed(_));
         match message {
             WorkerMessage::Data(payload) => {
-                let webhook_url = payload.webhook_url();
-                let payload_json = payload.serialize();
-                println!("sending discord message: {}", &payload_json);
-
-                let mut retries = 0;
-                while retries < MAX_RETRIES {
-                    match client
-                        .post(webhook_url)
-                        .header("Content-Type", "application/json")
-                        .body(payload_json.clone())
-                        .send()
-                        .await
-                    {
-                        Ok(res) => {
-                            debug_println!("webhook message sent: {:?}", &res);
-                            let res_text = res.text().await.unwrap();
-                            debug_println!("webhook message response: {}", res_text);
-                            break; // Success, break out of the retry loop
-                        }
-                        Err(e) => {
-                            println!("ERROR: failed to send webhook message: {}", e);
-                        }
-                    };
-
-                    // Exponential backoff - increase the delay between retries
-                    let delay_ms = 2u64.pow(retries as u32) * 100;
-                    tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
-                    retries += 1;
+                dispatcher.send_limited(rate_limiter.as_mut(), payload).await;
+            }
+            WorkerMessage::Event(inputs) | WorkerMessage::Bypassed(inputs) => {
+                options.queue.pop();
+                if let Some(backpressure) = options.backpressure.as_ref().filter(|_| !bypassed) {
+                    backpressure.release();
+                }
+                if let Some(digest) = digest.as_mut().filter(|digest| digest.accepts(&inputs)) {
+                    digest.push(*inputs);
+                    continue;
+                }
+                let inputs = grouping.push(*inputs, Instant::now());
+                for payload in batched(batcher.as_mut(), inputs, Instant::now())
+                    .into_iter()
+                    .flat_map(&*events)
+                {
+                    dispatcher.send_limited(rate_limiter.as_mut(), payload).await;
+                }
+            }
+            WorkerMessage::Notice(notice) => {
+                for payload in notices(&notice) {
+                    dispatcher.send(payload, false).await;
+                }
+            }
+            WorkerMessage::Flush(_) | WorkerMessage::FlushAsync(_) => {
+                // Queued events were sent before the flush, so they are sent now.
+                let batch = batcher.as_mut().map(Batcher::take).unwrap_or_default();
+                for payload in events(batch) {
+                    dispatcher.send_limited(rate_limiter.as_mut(), payload).await;
+                }
+                dispatcher.flush().await;
+                match message {
+                    WorkerMessage::Flush(ack) => {
+                        let _ = ack.send(());
+                    }
+                    WorkerMessage::FlushAsync(ack) => {
+                        let _ = ack.send(());
+                    }
+                    _ => {}
                 }
             }
             WorkerMessage::Shutdown => {
+                let mut batch = batcher.as_mut().map(Batcher::take).unwrap_or_default();
+                batch.extend(grouping.take_all());
+                for payload in events(batch) {
+                    dispatcher.send_limited(rate_limiter.as_mut(), payload).await;
+                }
+                let digest = digest.as_mut().and_then(Digest::take_notice);
+                let summary = rate_limiter.as_mut().and_then(RateLimiter::take_summary);
+                for notice in digest.iter().chain(summary.iter()).chain(Some(&Notice::Shutdown)) {
+                    for payload in notices(notice) {
+                        dispatcher.send(payload, false).await;
+                    }
+                }
                 break;
             }
         }
     }
+    dispatcher.shutdown().await;
+    if let Some(backpressure) = &options.backpressure {
+        backpressure.close();
+    }
+}
+
+/// What woke the worker up: a message, or one of its timers.
+enum Wake {
+    Message(Option<WorkerMessage>),
+    Heartbeat,
+    Summary,
+    Digest,
+    Grouping,
+    Batch,
+}
+
+/// Tracks when the events queued for the worker were emitted, to report how far delivery is behind.
+///
+/// Events are received in the order they were queued, so the oldest time belongs to the next event
+/// received. Errors which are [prioritized](WorkerOptions::prioritize_errors) skip the queue, so the
+/// oldest age is only an estimate then.
+#[derive(Debug, Default)]
+pub(crate) struct QueueGauge {
+    queued: std::sync::Mutex<VecDeque<std::time::Instant>>,
+}
+
+impl QueueGauge {
+    /// Record that an event is about to be queued.
+    pub(crate) fn push(&self) {
+        let mut queued = lock(&self.queued);
+        queued.push_back(std::time::Instant::now());
+        Self::publish(&queued);
+    }
+
+    /// Record that an event was received, or could not be queued after all.
+    pub(crate) fn pop(&self) {
+        let mut queued = lock(&self.queued);
+        queued.pop_front();
+        Self::publish(&queued);
+    }
+
+    /// Update the gauges of the `metrics` facade, whenever an event is queued or received.
+    #[cfg(feature = "metrics")]
+    fn publish(queued: &VecDeque<std::time::Instant>) {
+        metrics::gauge!(QUEUED_METRIC).set(queued.len() as f64);
+        let oldest = queued.front().map_or(Duration::ZERO, std::time::Instant::elapsed);
+        metrics::gauge!(OLDEST_QUEUED_METRIC).set(oldest.as_secs_f64());
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    fn publish(_queued: &VecDeque<std::time::Instant>) {}
+
+    pub(crate) fn depth(&self) -> usize {
+        lock(&self.queued).len()
+    }
+
+    pub(crate) fn oldest_age(&self) -> Option<Duration> {
+        lock(&self.queued).front().map(std::time::Instant::elapsed)
+    }
+}
+
+/// Bounds the number of events queued for the worker, so the threads emitting events block while the
+/// queue is full instead of queuing events faster than they can be delivered.
+///
+/// Every queued event holds a slot of a bounded channel, which the worker frees once it received the event.
+/// An event takes a free slot if there is one, and otherwise blocks its thread until the worker frees one.
+/// Blocking cannot block the worker on a multi-threaded runtime, as the worker runs on another thread. On a
+/// current-thread runtime, or on the worker's own tasks, e.g. from the delivery hooks, it would park the
+/// thread which has to free the slot, so these events are queued beyond the capacity instead, and counted
+/// as [bypassed](WorkerMetrics::bypassed).
+///
+/// Backpressure only ever blocks: `on_event` is synchronous, so there is no way to await a free slot, and
+/// events are never dropped in favor of blocking.
+pub(crate) struct Backpressure {
+    slots: async_channel::Sender<()>,
+    freed: async_channel::Receiver<()>,
+    stats: Arc<DeliveryStats>,
+}
+
+impl Backpressure {
+    pub(crate) fn new(capacity: usize, stats: Arc<DeliveryStats>) -> Self {
+        let (slots, freed) = async_channel::bounded(capacity.max(1));
+        Self { slots, freed, stats }
+    }
+
+    /// Take a slot of the queue for an event, waiting until the worker frees one while the queue is full.
+    /// Returns whether the event holds a slot, rather than being queued beyond the capacity because the
+    /// current thread must not block.
+    ///
+    /// Once the worker stopped, no event can be queued anyway, so no event waits for a slot.
+    pub(crate) fn acquire(&self) -> bool {
+        match self.slots.try_send(()) {
+            Err(async_channel::TrySendError::Full(())) if on_current_thread_runtime() || on_worker() => {
+                self.stats.record_bypassed();
+                false
+            }
+            Err(async_channel::TrySendError::Full(())) => {
+                let _ = self.slots.send_blocking(());
+                true
+            }
+            Ok(()) | Err(async_channel::TrySendError::Closed(())) => true,
+        }
+    }
+
+    /// Free the slot of an event which the worker received.
+    pub(crate) fn release(&self) {
+        let _ = self.freed.try_recv();
+    }
+
+    /// Stop blocking, once the worker stopped.
+    pub(crate) fn close(&self) {
+        self.slots.close();
+    }
+}
+
+/// Whether the current thread runs a current-thread tokio runtime, which also runs the worker.
+#[cfg(feature = "tokio")]
+fn on_current_thread_runtime() -> bool {
+    tokio::runtime::Handle::try_current()
+        .map(|handle| handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::CurrentThread)
+        .unwrap_or(false)
+}
+
+#[cfg(not(feature = "tokio"))]
+fn on_current_thread_runtime() -> bool {
+    false
+}
+
+/// The messages received from the layer, which are handled in order, except that ERROR events jump the
+/// queue if errors are prioritized, so they are not delayed by a backlog of less severe events.
+///
+/// Flushes and the shutdown are barriers: ERROR events only jump ahead of the events queued before the
+/// next barrier, and the messages behind a barrier are only received once it was handled, so a flush
+/// still waits for every message sent before it, and no message sent after it is handled before it.
+pub(crate) struct Lanes {
+    prioritize_errors: bool,
+    errors: VecDeque<WorkerMessage>,
+    others: VecDeque<WorkerMessage>,
+    /// Whether the last message of `others` is a barrier.
+    at_barrier: bool,
+}
+
+impl Lanes {
+    pub(crate) fn new(prioritize_errors: bool) -> Self {
+        Self {
+            prioritize_errors,
+            errors: VecDeque::new(),
+            others: VecDeque::new(),
+            at_barrier: false,
+        }
+    }
+
+    fn is_error(message: &WorkerMessage) -> bool {
+        match message {
+            WorkerMessage::Event(inputs) | WorkerMessage::Bypassed(inputs) => inputs.event_level == Level::ERROR,
+            _ => false,
+        }
+    }
+
+    /// The next message to handle, or `None` once every sender was dropped.
+    pub(crate) async fn next(&mut self, rx: &mut ChannelReceiver) -> Option<WorkerMessage> {
+        if !self.prioritize_errors {
+            return rx.recv().await.ok();
+        }
+        while !self.at_barrier {
+            match rx.try_recv() {
+                Ok(message) if Self::is_error(&message) => self.errors.push_back(message),
+                Ok(message) => {
+                    self.at_barrier = matches!(
+                        message,
+                        WorkerMessage::Flush(_) | WorkerMessage::FlushAsync(_) | WorkerMessage::Shutdown
+                    );
+                    self.others.push_back(message);
+                }
+                Err(_) => break,
+            }
+        }
+        if let Some(message) = self.errors.pop_front() {
+            return Some(message);
+        }
+        match self.others.pop_front() {
+            Some(message) => {
+                // The barrier is handled once every message ahead of it was.
+                if self.others.is_empty() {
+                    self.at_barrier = false;
+                }
+                Some(message)
+            }
+            None => rx.recv().await.ok(),
+        }
+    }
+}
+
+/// The batches of events which should be sent now: every event on its own without a batcher, and
+/// otherwise the batch which was filled by the events, if any.
+fn batched(
+    batcher: Option<&mut Batcher>,
+    inputs: Vec<WebhookMessageInputs>,
+    now: Instant,
+) -> Vec<Vec<WebhookMessageInputs>> {
+    match batcher {
+        Some(batcher) => batcher.push(inputs, now).into_iter().collect(),
+        None => inputs.into_iter().map(|inputs| vec![inputs]).collect(),
+    }
+}
+
+/// Ticks once per period, starting one period after it was created. Ticks which were missed, e.g. while
+/// the worker was delivering a message, are skipped.
+pub(crate) struct Ticker {
+    period: Duration,
+    next: Instant,
+}
+
+impl Ticker {
+    pub(crate) fn new(period: Duration) -> Self {
+        Self {
+            period,
+            next: Instant::now() + period,
+        }
+    }
+}
+
+/// Wait for the next tick of a ticker, or forever without one.
+pub(crate) async fn tick(runtime: &dyn Runtime, ticker: Option<&mut Ticker>) {
+    match ticker {
+        Some(ticker) => {
+            sleep_until(runtime, Some(ticker.next)).await;
+            let now = Instant::now();
+            ticker.next += ticker.period;
+            if ticker.next <= now {
+                ticker.next = now + ticker.period;
+            }
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait until a deadline, or forever without one.
+async fn sleep_until(runtime: &dyn Runtime, deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => runtime.sleep(deadline.saturating_duration_since(Instant::now())).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for a future for at most `timeout`, returning whether it completed in time.
+async fn within(runtime: &dyn Runtime, timeout: Duration, future: impl Future) -> bool {
+    let completed = async {
+        future.await;
+        true
+    };
+    let timed_out = async {
+        runtime.sleep(timeout).await;
+        false
+    };
+    futures_lite::future::or(completed, timed_out).await
+}
+
+/// A token bucket limiting how many messages are delivered per interval, which counts the messages it
+/// suppresses so they can be summarized once the interval is over.
+pub(crate) struct RateLimiter {
+    max_messages: f64,
+    interval: Duration,
+    tokens: f64,
+    refilled_at: Instant,
+    suppressed: usize,
+    /// When the suppressed messages should be summarized, if any were suppressed.
+    summary_due: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(max_messages: u32, interval: Duration) -> Self {
+        Self {
+            max_messages: max_messages as f64,
+            interval,
+            tokens: max_messages as f64,
+            refilled_at: Instant::now(),
+            suppressed: 0,
+            summary_due: None,
+        }
+    }
+
+    /// Take a token for delivering a message, or count the message as suppressed if there is none.
+    pub(crate) fn try_acquire(&mut self, now: Instant) -> bool {
+        // Tokens are refilled continuously, so bursts are spread over the interval.
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        let refill = elapsed * self.max_messages / self.interval.as_secs_f64().max(f64::EPSILON);
+        self.tokens = (self.tokens + refill).min(self.max_messages);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return true;
+        }
+        self.suppressed += 1;
+        self.summary_due.get_or_insert(now + self.interval);
+        false
+    }
+
+    /// The notice summarizing the suppressed messages, if any were suppressed since the last summary.
+    pub(crate) fn take_summary(&mut self) -> Option<Notice> {
+        self.summary_due = None;
+        match std::mem::take(&mut self.suppressed) {
+            0 => None,
+            messages => Some(Notice::Suppressed {
+                messages,
+                interval: self.interval,
+            }),
+        }
+    }
+}
+
+/// The stages grouping events before they are sent, each of which is optional.
+struct Grouping {
+    deduplicator: Option<Deduplicator>,
+    cooldown: Option<Cooldown>,
+}
+
+impl Grouping {
+    /// Pass an event through every stage, returning the events which should be sent now.
+    fn push(&mut self, inputs: WebhookMessageInputs, now: Instant) -> Vec<WebhookMessageInputs> {
+        let inputs = match &mut self.cooldown {
+            Some(cooldown) => match cooldown.push(inputs, now) {
+                Some(inputs) => inputs,
+                None => return Vec::new(),
+            },
+            None => inputs,
+        };
+        match &mut self.deduplicator {
+            Some(deduplicator) => deduplicator.push(inputs, now),
+            None => vec![inputs],
+        }
+    }
+
+    /// When the next stage is due to send a summary, if any stage holds events.
+    fn due(&self) -> Option<Instant> {
+        let deduplicator = self.deduplicator.as_ref().and_then(Deduplicator::due);
+        let cooldown = self.cooldown.as_ref().and_then(Cooldown::due);
+        deduplicator.into_iter().chain(cooldown).min()
+    }
+
+    /// The summaries which are due to be sent.
+    fn take_due(&mut self, now: Instant) -> Vec<WebhookMessageInputs> {
+        let mut summaries = Vec::new();
+        if let Some(deduplicator) = &mut self.deduplicator {
+            if deduplicator.due().is_some_and(|due| due <= now) {
+                summaries.extend(deduplicator.take_summary());
+            }
+        }
+        if let Some(cooldown) = &mut self.cooldown {
+            summaries.extend(cooldown.take_expired(now));
+        }
+        summaries
+    }
+
+    /// The summaries of every stage, e.g. when the worker shuts down.
+    fn take_all(&mut self) -> Vec<WebhookMessageInputs> {
+        let mut summaries: Vec<WebhookMessageInputs> = self
+            .deduplicator
+            .as_mut()
+            .and_then(Deduplicator::take_summary)
+            .into_iter()
+            .collect();
+        if let Some(cooldown) = &mut self.cooldown {
+            summaries.extend(cooldown.take_all());
+        }
+        summaries
+    }
+}
+
+/// Groups consecutive identical events, so an event repeated within a window is sent once when it first
+/// occurs, and once more when the window is over, annotated with the number of occurrences.
+pub(crate) struct Deduplicator {
+    window: Duration,
+    fingerprint: Fingerprint,
+    group: Option<EventGroup>,
+}
+
+/// Consecutive events with the same fingerprint.
+struct EventGroup {
+    fingerprint: String,
+    started_at: Instant,
+    occurrences: usize,
+    /// The latest of the events, which is sent to summarize the group.
+    latest: WebhookMessageInputs,
+}
+
+impl Deduplicator {
+    pub(crate) fn new(window: Duration, fingerprint: Fingerprint) -> Self {
+        Self {
+            window,
+            fingerprint,
+            group: None,
+        }
+    }
+
+    /// Add an event to the current group if it has the group's fingerprint, returning the events
+    /// which should be sent now: none if it was grouped, and otherwise the summary of the previous group,
+    /// if it repeated, followed by the event itself.
+    pub(crate) fn push(&mut self, inputs: WebhookMessageInputs, now: Instant) -> Vec<WebhookMessageInputs> {
+        let fingerprint = (self.fingerprint)(&inputs);
+        if let Some(group) = &mut self.group {
+            if group.fingerprint == fingerprint && now < group.started_at + self.window {
+                group.occurrences += 1;
+                group.latest = inputs;
+                return Vec::new();
+            }
+        }
+        let mut send: Vec<WebhookMessageInputs> = self.take_summary().into_iter().collect();
+        self.group = Some(EventGroup {
+            fingerprint,
+            started_at: now,
+            occurrences: 1,
+            latest: inputs.clone(),
+        });
+        send.push(inputs);
+        send
+    }
+
+    /// When the current group's window is over, if there is a group.
+    pub(crate) fn due(&self) -> Option<Instant> {
+        self.group.as_ref().map(|group| group.started_at + self.window)
+    }
+
+    /// Close the current group, returning its latest event annotated with the number of occurrences
+    /// (e.g. `Retrying request (×37 in the last 5m)`) if the event repeated.
+    pub(crate) fn take_summary(&mut self) -> Option<WebhookMessageInputs> {
+        let group = self.group.take().filter(|group| group.occurrences > 1)?;
+        let mut summary = group.latest;
+        summary.message = format!(
+            "{} (×{} in the last {})",
+            summary.message,
+            group.occurrences,
+            format_interval(self.window)
+        );
+        Some(summary)
+    }
+}
+
+/// Silences the fingerprint of every sent event for a period, so further occurrences are only counted,
+/// and summarized by sending the latest occurrence once the period is over.
+pub(crate) struct Cooldown {
+    period: Duration,
+    fingerprint: Fingerprint,
+    silenced: HashMap<String, SilencedEvent>,
+}
+
+/// The occurrences of an event silenced until the cooldown of its fingerprint is over.
+struct SilencedEvent {
+    until: Instant,
+    occurrences: usize,
+    latest: Option<WebhookMessageInputs>,
+}
+
+impl Cooldown {
+    pub(crate) fn new(period: Duration, fingerprint: Fingerprint) -> Self {
+        Self {
+            period,
+            fingerprint,
+            silenced: HashMap::new(),
+        }
+    }
+
+    /// Return the event if it should be sent, starting its fingerprint's cooldown, or count it if its
+    /// fingerprint is silenced.
+    pub(crate) fn push(&mut self, inputs: WebhookMessageInputs, now: Instant) -> Option<WebhookMessageInputs> {
+        let fingerprint = (self.fingerprint)(&inputs);
+        match self.silenced.get_mut(&fingerprint) {
+            Some(silenced) if now < silenced.until => {
+                silenced.occurrences += 1;
+                silenced.latest = Some(inputs);
+                None
+            }
+            _ => {
+                let silenced = SilencedEvent {
+                    until: now + self.period,
+                    occurrences: 0,
+                    latest: None,
+                };
+                self.silenced.insert(fingerprint, silenced);
+                Some(inputs)
+            }
+        }
+    }
+
+    /// When the earliest cooldown is over, if any fingerprint is silenced.
+    pub(crate) fn due(&self) -> Option<Instant> {
+        self.silenced.values().map(|silenced| silenced.until).min()
+    }
+
+    /// End the cooldowns which are over, returning the summaries of the events silenced during them.
+    pub(crate) fn take_expired(&mut self, now: Instant) -> Vec<WebhookMessageInputs> {
+        let expired: Vec<String> = self
+            .silenced
+            .iter()
+            .filter(|(_, silenced)| silenced.until <= now)
+            .map(|(fingerprint, _)| fingerprint.clone())
+            .collect();
+        let silenced = expired
+            .iter()
+            .filter_map(|fingerprint| self.silenced.remove(fingerprint))
+            .collect();
+        self.summarize(silenced)
+    }
+
+    /// End every cooldown, returning the summaries of the events silenced during them.
+    pub(crate) fn take_all(&mut self) -> Vec<WebhookMessageInputs> {
+        let silenced = self.silenced.drain().map(|(_, silenced)| silenced).collect();
+        self.summarize(silenced)
+    }
+
+    /// The latest occurrence of each silenced event, annotated with the number of silenced occurrences
+    /// (e.g. `Payment failed (×12 more in the last 10m)`), in the order the cooldowns end.
+    fn summarize(&self, mut silenced: Vec<SilencedEvent>) -> Vec<WebhookMessageInputs> {
+        silenced.sort_by_key(|silenced| silenced.until);
+        silenced
+            .into_iter()
+            .filter_map(|silenced| {
+                let mut summary = silenced.latest?;
+                summary.message = format!(
+                    "{} (×{} more in the last {})",
+                    summary.message,
+                    silenced.occurrences,
+                    format_interval(self.period)
+                );
+                Some(summary)
+            })
+            .collect()
+    }
+}
+
+/// Queues events to be sent together, until enough events are queued or the first of them was queued
+/// for long enough.
+pub(crate) struct Batcher {
+    max_size: usize,
+    max_latency: Duration,
+    queue: Vec<WebhookMessageInputs>,
+    /// When the first queued event was queued.
+    queued_at: Option<Instant>,
+}
+
+impl Batcher {
+    pub(crate) fn new(max_size: usize, max_latency: Duration) -> Self {
+        Self {
+            max_size: max_size.max(1),
+            max_latency,
+            queue: Vec::new(),
+            queued_at: None,
+        }
+    }
+
+    /// Queue events, returning the batch which should be sent now if the queue is full.
+    pub(crate) fn push(
+        &mut self,
+        inputs: Vec<WebhookMessageInputs>,
+        now: Instant,
+    ) -> Option<Vec<WebhookMessageInputs>> {
+        if inputs.is_empty() {
+            return None;
+        }
+        self.queued_at.get_or_insert(now);
+        self.queue.extend(inputs);
+        (self.queue.len() >= self.max_size).then(|| self.take())
+    }
+
+    /// When the queued events are due to be sent, if any are queued.
+    pub(crate) fn due(&self) -> Option<Instant> {
+        self.queued_at.map(|queued_at| queued_at + self.max_latency)
+    }
+
+    /// Take every queued event.
+    pub(crate) fn take(&mut self) -> Vec<WebhookMessageInputs> {
+        self.queued_at = None;
+        std::mem::take(&mut self.queue)
+    }
+}
+
+/// Accumulates events less severe than a level, so they are sent together as one digest per interval.
+pub(crate) struct Digest {
+    interval: Duration,
+    level: Level,
+    counts: BTreeMap<(String, Level), usize>,
+    samples: Vec<String>,
+}
+
+impl Digest {
+    pub(crate) fn new(interval: Duration, level: Level) -> Self {
+        Self {
+            interval,
+            level,
+            counts: BTreeMap::new(),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Whether an event should be digested, rather than sent on its own.
+    pub(crate) fn accepts(&self, inputs: &WebhookMessageInputs) -> bool {
+        // More verbose levels compare as greater.
+        inputs.event_level > self.level
+    }
+
+    pub(crate) fn push(&mut self, inputs: WebhookMessageInputs) {
+        *self.counts.entry((inputs.target, inputs.event_level)).or_default() += 1;
+        if self.samples.len() < DIGEST_SAMPLES {
+            self.samples.push(inputs.message);
+        }
+    }
+
+    /// The notice announcing the events digested since the previous digest, if there were any.
+    pub(crate) fn take_notice(&mut self) -> Option<Notice> {
+        if self.counts.is_empty() {
+            return None;
+        }
+        let counts = std::mem::take(&mut self.counts)
+            .into_iter()
+            .map(|((target, level), events)| DigestCount { target, level, events })
+            .collect();
+        Some(Notice::Digest {
+            interval: self.interval,
+            counts,
+            samples: std::mem::take(&mut self.samples),
+        })
+    }
+}
+
+/// Delivers messages through the sink, retrying failed deliveries according to the retry policy.
+///
+/// The state of the circuit breaker, the fallback, and the persistent queue is shared by the clones of a
+/// delivery, so concurrent senders trip the same breaker.
+#[derive(Clone)]
+struct Delivery {
+    sink: Arc<dyn Sink>,
+    retry: RetryPolicy,
+    breaker: Option<Arc<std::sync::Mutex<CircuitBreaker>>>,
+    fallback: Option<Arc<std::sync::Mutex<FallbackWriter>>>,
+    log: Option<Arc<std::sync::Mutex<MessageLog>>>,
+    stats: Arc<DeliveryStats>,
+    on_error: Option<DeliveryErrorHook>,
+    on_delivered: Option<DeliveredHook>,
+    runtime: Arc<dyn Runtime>,
+}
+
+/// The outcome of delivering a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Delivered,
+    /// The endpoint rejected the message, so delivering it again would fail as well.
+    Rejected,
+    /// Every attempt failed, e.g. because the endpoint is unreachable.
+    Failed,
+}
+
+impl Delivery {
+    /// Deliver a message, unless the circuit breaker is open. Returns whether the message was delivered.
+    async fn deliver(&self, payload: &dyn WebhookMessage) -> bool {
+        let id = self.log.as_ref().and_then(|log| lock(log).append(payload));
+        self.send(payload, id).await
+    }
+
+    /// Deliver a message which has the given ID in the persistent queue, if any. The message is removed
+    /// from the queue once it is delivered or rejected, and kept to be delivered again on the next start
+    /// otherwise.
+    async fn send(&self, payload: &dyn WebhookMessage, id: Option<u64>) -> bool {
+        if let Some(breaker) = &self.breaker {
+            if !lock(breaker).allows(Instant::now()) {
+                self.write_fallback(payload);
+                self.stats.record(false);
+                return false;
+            }
+        }
+        let (outcome, error) = self.attempt(payload).await;
+        if let (Some(on_error), Some(error)) = (&self.on_error, error) {
+            if let Err(message) = recover(|| on_error(error)) {
+                println!(
+                    "ERROR: webhook message worker recovered from a panic of the delivery error hook: {}",
+                    message
+                );
+            }
+        }
+        if outcome != Outcome::Delivered {
+            self.write_fallback(payload);
+        }
+        if outcome != Outcome::Failed {
+            if let (Some(log), Some(id)) = (&self.log, id) {
+                lock(log).ack(id);
+            }
+        }
+        if let Some(breaker) = &self.breaker {
+            let mut breaker = lock(breaker);
+            match outcome {
+                Outcome::Delivered => breaker.record_success(Instant::now()),
+                Outcome::Failed => breaker.record_failure(Instant::now()),
+                Outcome::Rejected => {}
+            }
+        }
+        self.stats.record(outcome == Outcome::Delivered);
+        outcome == Outcome::Delivered
+    }
+
+    fn write_fallback(&self, payload: &dyn WebhookMessage) {
+        if let Some(fallback) = &self.fallback {
+            lock(fallback).write(payload);
+        }
+    }
+
+    /// The notice announcing that delivery recovered, once after the circuit breaker closed.
+    fn take_recovery(&self) -> Option<Notice> {
+        self.breaker.as_ref().and_then(|breaker| lock(breaker).take_recovery())
+    }
+
+    /// Deliver a message, retrying failed deliveries which are retryable.
+    ///
+    /// Returns the error of the last attempt as well if the message was not delivered.
+    async fn attempt(&self, payload: &dyn WebhookMessage) -> (Outcome, Option<DeliveryError>) {
+        // Webhook URLs usually embed a secret, so only their host is printed.
+        let webhook_url = redact_url(payload.webhook_url());
+
+        let max_attempts = self.retry.max_attempts();
+        let mut attempt = 1;
+        let mut pauses = 0;
+        let mut last_error = None;
+        while attempt <= max_attempts {
+            let delivered = Recover(self.sink.deliver(payload))
+                .await
+                .unwrap_or_else(|message| Err(DeliveryError::Rejected(format!("the sink panicked: {}", message))));
+            match delivered {
+                Ok(delivered) => {
+                    if let Some(on_delivered) = &self.on_delivered {
+                        if let Err(message) = recover(|| on_delivered(payload, delivered.status)) {
+                            println!(
+                                "ERROR: webhook message worker recovered from a panic of the delivered hook: {}",
+                                message
+                            );
+                        }
+                    }
+                    return (Outcome::Delivered, None);
+                }
+                // Waiting as long as the endpoint asks to does not use up an attempt, since rate limited
+                // requests were not processed. Every later message waits as well, as they are sent in order.
+                Err(DeliveryError::RateLimited {
+                    retry_after: Some(retry_after),
+                }) if pauses < MAX_RATE_LIMIT_PAUSES => {
+                    self.stats.record_rate_limited();
+                    self.stats.record_retried();
+                    let retry_after = retry_after.min(MAX_RETRY_AFTER);
+                    println!(
+                        "ERROR: webhook {} is rate limited, pausing for {}",
+                        webhook_url,
+                        format_interval(retry_after)
+                    );
+                    self.runtime.sleep(retry_after).await;
+                    pauses += 1;
+                    continue;
+                }
+                Err(e) => {
+                    println!("ERROR: failed to send webhook message to {}: {}", webhook_url, e);
+                    if let DeliveryError::RateLimited { .. } = e {
+                        self.stats.record_rate_limited();
+                    }
+                    if !e.is_retryable() {
+                        return (Outcome::Rejected, Some(e));
+                    }
+                    last_error = Some(e);
+                }
+            }
+            if attempt < max_attempts {
+                self.stats.record_retried();
+                self.runtime.sleep(self.retry.delay(attempt)).await;
+            }
+            attempt += 1;
+        }
+        println!(
+            "ERROR: giving up on webhook message to {} after {} attempts",
+            webhook_url, max_attempts
+        );
+        (Outcome::Failed, last_error)
+    }
+}
+
+/// Hands the messages of the worker to its senders: delivered one after another by the worker itself, or
+/// concurrently by sender tasks.
+///
+/// With concurrent senders, messages are assigned to a sender by their
+/// [ordering key](WebhookMessage::ordering_key), and the messages of a thread to the same sender, so the
+/// messages posted to a destination are still delivered in order.
+struct Dispatcher {
+    delivery: Delivery,
+    senders: Vec<async_channel::Sender<SenderMessage>>,
+    tasks: Vec<Completion>,
+    /// The number of messages of events delivered since it was last taken.
+    delivered: Arc<AtomicUsize>,
+}
+
+/// A command sent to a sender task.
+enum SenderMessage {
+    /// Deliver a message, counting it as a message of events if it is delivered.
+    Deliver(Box<dyn WebhookMessage>, bool),
+    /// Acknowledge once every message sent before was delivered.
+    Flush(futures_channel::oneshot::Sender<()>),
+}
+
+impl Dispatcher {
+    fn new(delivery: Delivery, concurrency: usize) -> Self {
+        let delivered = Arc::new(AtomicUsize::new(0));
+        let (senders, tasks) = if concurrency > 1 {
+            (0..concurrency)
+                .map(|_| {
+                    let (tx, rx) = async_channel::unbounded();
+                    let task = OnWorker(Box::pin(sender(delivery.clone(), rx, delivered.clone())));
+                    (tx, runtime::spawn(&*delivery.runtime, task))
+                })
+                .unzip()
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        Self {
+            delivery,
+            senders,
+            tasks,
+            delivered,
+        }
+    }
+
+    /// Deliver a message of events unless the rate limit suppresses it.
+    async fn send_limited(&self, rate_limiter: Option<&mut RateLimiter>, payload: Box<dyn WebhookMessage>) {
+        if let Some(rate_limiter) = rate_limiter {
+            if !rate_limiter.try_acquire(Instant::now()) {
+                self.delivery.stats.record_enqueued();
+                self.delivery.stats.record_rate_limited();
+                self.delivery.stats.record(false);
+                return;
+            }
+        }
+        self.send(payload, true).await
+    }
+
+    /// Deliver a message, counting it as a message of events if it is delivered.
+    async fn send(&self, payload: Box<dyn WebhookMessage>, counted: bool) {
+        self.delivery.stats.record_enqueued();
+        if self.senders.is_empty() {
+            if self.delivery.deliver(payload.as_ref()).await && counted {
+                self.delivered.fetch_add(1, Ordering::Relaxed);
+            }
+            return;
+        }
+        let mut hasher = DefaultHasher::new();
+        match payload.thread() {
+            Some(thread) => thread.id().hash(&mut hasher),
+            None => payload.ordering_key().hash(&mut hasher),
+        }
+        let sender = &self.senders[hasher.finish() as usize % self.senders.len()];
+        if sender.try_send(SenderMessage::Deliver(payload, counted)).is_err() {
+            println!("ERROR: failed to send webhook message to a sender task");
+        }
+    }
+
+    /// Wait until every message sent before was delivered.
+    async fn flush(&self) {
+        let mut flushed = Vec::new();
+        for sender in &self.senders {
+            let (ack, done) = futures_channel::oneshot::channel();
+            if sender.try_send(SenderMessage::Flush(ack)).is_ok() {
+                flushed.push(done);
+            }
+        }
+        for done in flushed {
+            let _ = done.await;
+        }
+    }
+
+    /// Wait until the sender tasks delivered every message, and stop them.
+    async fn shutdown(self) {
+        drop(self.senders);
+        for task in self.tasks {
+            let _ = task.await;
+        }
+    }
+
+    /// The number of messages of events delivered since this was last called.
+    fn take_delivered(&self) -> usize {
+        self.delivered.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// Delivers the messages sent to a sender task, in order.
+async fn sender(delivery: Delivery, rx: async_channel::Receiver<SenderMessage>, delivered: Arc<AtomicUsize>) {
+    while let Ok(message) = rx.recv().await {
+        match message {
+            SenderMessage::Deliver(payload, counted) => {
+                if delivery.deliver(payload.as_ref()).await && counted {
+                    delivered.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            SenderMessage::Flush(ack) => {
+                let _ = ack.send(());
+            }
+        }
+    }
+}
+
+thread_local! {
+    /// Whether the current thread is running code of the worker which recovers from panics.
+    static RECOVERING_PANICS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether a panic on the current thread is recovered from by the worker, rather than unwinding the
+/// thread.
+pub(crate) fn recovering_panics() -> bool {
+    RECOVERING_PANICS.with(Cell::get)
+}
+
+thread_local! {
+    /// Whether the current thread is polling one of the worker's tasks.
+    static ON_WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether the current thread is polling one of the worker's tasks, e.g. because a delivery hook emitted
+/// an event.
+pub(crate) fn on_worker() -> bool {
+    ON_WORKER.with(Cell::get)
+}
+
+/// A task of the worker, marking the thread polling it so events emitted from the task are told apart.
+pub(crate) struct OnWorker<F>(pub(crate) F);
+
+impl<F: Future + Unpin> Future for OnWorker<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let on_worker = ON_WORKER.with(|flag| flag.replace(true));
+        let poll = Pin::new(&mut self.0).poll(cx);
+        ON_WORKER.with(|flag| flag.set(on_worker));
+        poll
+    }
+}
+
+/// Run code of the factory or the sink, recovering from its panics, so a bad payload or a bug does not
+/// stop the worker. Returns the message of the panic, if it panicked.
+fn recover<T>(run: impl FnOnce() -> T) -> Result<T, String> {
+    let recovering = RECOVERING_PANICS.with(|recovering| recovering.replace(true));
+    let result = std::panic::catch_unwind(AssertUnwindSafe(run));
+    RECOVERING_PANICS.with(|flag| flag.set(recovering));
+    result.map_err(|panic| panic_message(panic.as_ref()).to_string())
+}
+
+/// Render the messages of events or notices, dropping them if the factory panics.
+fn render<T>(what: &str, render: impl FnOnce() -> Vec<T>) -> Vec<T> {
+    recover(render).unwrap_or_else(|message| {
+        println!(
+            "ERROR: webhook message worker recovered from a panic while rendering {}: {}",
+            what, message
+        );
+        Vec::new()
+    })
+}
+
+/// A future recovering from the panics of the future it wraps, e.g. of a sink delivering a message.
+struct Recover<F>(F);
+
+impl<F: Future + Unpin> Future for Recover<F> {
+    type Output = Result<F::Output, String>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match recover(|| Pin::new(&mut self.0).poll(cx)) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(message) => Poll::Ready(Err(message)),
+        }
+    }
+}
+
+/// Lock a mutex, even if a thread panicked while holding it.
+fn lock<T>(mutex: &std::sync::Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Stops attempting deliveries after consecutive failed deliveries, until a cool-off period is over,
+/// counting the messages which were not delivered so they can be summarized once delivery recovers.
+///
+/// Once the cool-off period is over, the next message is attempted: the breaker closes if it is
+/// delivered, and opens for another cool-off period otherwise.
+pub(crate) struct CircuitBreaker {
+    threshold: u32,
+    cool_off: Duration,
+    /// The number of consecutive failed deliveries.
+    failures: u32,
+    /// When the breaker opened, while it is open.
+    opened_at: Option<Instant>,
+    /// When the next delivery may be attempted, while the breaker is open.
+    retry_at: Option<Instant>,
+    /// The number of messages which were not delivered since the breaker opened.
+    dropped: usize,
+    recovery: Option<Notice>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(threshold: u32, cool_off: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            cool_off,
+            failures: 0,
+            opened_at: None,
+            retry_at: None,
+            dropped: 0,
+            recovery: None,
+        }
+    }
+
+    /// Whether a delivery may be attempted now, counting the message as dropped otherwise.
+    pub(crate) fn allows(&mut self, now: Instant) -> bool {
+        match self.retry_at {
+            Some(retry_at) if now < retry_at => {
+                self.dropped += 1;
+                false
+            }
+            _ => true,
+        }
+    }
+
+    pub(crate) fn record_failure(&mut self, now: Instant) {
+        self.failures += 1;
+        if self.opened_at.is_some() || self.failures >= self.threshold {
+            if self.opened_at.is_none() {
+                println!(
+                    "ERROR: {} consecutive deliveries failed, pausing delivery",
+                    self.failures
+                );
+                // The failures which tripped the breaker were not delivered either.
+                self.dropped += self.failures as usize;
+            } else {
+                self.dropped += 1;
+            }
+            self.opened_at.get_or_insert(now);
+            self.retry_at = Some(now + self.cool_off);
+        }
+    }
+
+    pub(crate) fn record_success(&mut self, now: Instant) {
+        self.failures = 0;
+        self.retry_at = None;
+        if let Some(opened_at) = self.opened_at.take() {
+            self.recovery = Some(Notice::Recovered {
+                dropped: std::mem::take(&mut self.dropped),
+                outage: now.saturating_duration_since(opened_at),
+            });
+        }
+    }
+
+    /// The notice announcing that delivery recovered, if the breaker closed since the last notice.
+    pub(crate) fn take_recovery(&mut self) -> Option<Notice> {
+        self.recovery.take()
+    }
+}
+
+/// Strip everything but the scheme and host from a URL.
+fn redact_url(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(url) => format!("{}://{}/...", url.scheme(), url.host_str().unwrap_or_default()),
+        Err(_) => "<invalid url>".to_string(),
+    }
 }
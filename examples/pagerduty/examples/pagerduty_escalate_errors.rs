@@ -0,0 +1,30 @@
+use regex::Regex;
+use tracing::{error, info, instrument, warn, Level};
+use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+use tracing_layer_pagerduty::{EventFilters, PagerDutyLayer};
+use tracing_layer_slack::SlackLayer;
+
+#[instrument]
+pub async fn handler() {
+    info!("this should only be sent to slack");
+    warn!("this should only be sent to slack, too");
+    error!(
+        order_id = 42,
+        "this should be sent to slack and trigger a pagerduty alert"
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let targets_to_filter: EventFilters = Regex::new("escalate_errors").unwrap().into();
+    let (slack_layer, slack_worker) = SlackLayer::builder("test-app".to_string(), targets_to_filter.clone()).build();
+    let (pagerduty_layer, pagerduty_worker) = PagerDutyLayer::builder("test-app".to_string(), targets_to_filter)
+        .level_filter(Level::ERROR)
+        .build();
+    let subscriber = Registry::default().with(slack_layer).with(pagerduty_layer);
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+    handler().await;
+    slack_worker.shutdown().await;
+    pagerduty_worker.shutdown().await;
+}
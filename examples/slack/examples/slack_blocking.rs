@@ -0,0 +1,22 @@
+use tracing::{error, info};
+use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+use tracing_layer_slack::{EventFilters, SlackLayer};
+
+fn export(records: usize) -> Result<(), String> {
+    info!(records, "Nightly export started");
+    Err("disk full".to_string())
+}
+
+// A synchronous program, without a tokio runtime: the worker runs on a thread of its own.
+fn main() {
+    let targets = EventFilters::from_patterns(&["blocking"], &[]).expect("valid target pattern");
+    let (slack_layer, background_worker) = SlackLayer::builder("nightly-export".to_string(), targets).build_blocking();
+    let subscriber = Registry::default().with(slack_layer);
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+
+    if let Err(e) = export(1200) {
+        error!(error = %e, "Nightly export failed");
+    }
+    background_worker.shutdown_blocking();
+}
@@ -1,5 +1,5 @@
 use regex::Regex;
-use tracing::{debug, info, instrument};
+use tracing::{debug, info, instrument, Level};
 use tracing_subscriber::{layer::SubscriberExt, Registry};
 
 use tracing_layer_slack::{EventFilters, SlackLayer};
@@ -14,7 +14,7 @@ pub async fn handler() {
 async fn main() {
     let targets_to_filter: EventFilters = Regex::new("exclude_messages_below_level").unwrap().into();
     let (slack_layer, background_worker) = SlackLayer::builder("test-app".to_string(), targets_to_filter)
-        .level_filters("info".to_string())
+        .level_filter(Level::INFO)
         .build();
     let subscriber = Registry::default().with(slack_layer);
     tracing::subscriber::set_global_default(subscriber).unwrap();
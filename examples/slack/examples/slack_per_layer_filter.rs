@@ -0,0 +1,24 @@
+use regex::Regex;
+use tracing::{info, instrument, warn};
+use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, Layer, Registry};
+
+use tracing_layer_slack::{EventFilters, SlackLayer};
+
+#[instrument]
+pub async fn handler() {
+    info!("this should only be printed to stdout");
+    warn!("this should be printed to stdout and sent to slack");
+}
+
+#[tokio::main]
+async fn main() {
+    let targets_to_filter: EventFilters = Regex::new("per_layer_filter").unwrap().into();
+    let (slack_layer, background_worker) = SlackLayer::builder("test-app".to_string(), targets_to_filter).build();
+    // Per-layer filters only affect the layer they wrap, so other layers still see every event.
+    let subscriber = Registry::default()
+        .with(tracing_subscriber::fmt::layer())
+        .with(slack_layer.with_filter(LevelFilter::WARN));
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+    handler().await;
+    background_worker.shutdown().await;
+}
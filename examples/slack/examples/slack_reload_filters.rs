@@ -0,0 +1,28 @@
+use regex::Regex;
+use tracing::level_filters::LevelFilter;
+use tracing::{debug, info, instrument, Level};
+use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+use tracing_layer_slack::{EventFilters, SlackLayer};
+
+#[instrument]
+pub async fn handler() {
+    info!("this is one of the messages you should see");
+    debug!("this should be excluded");
+}
+
+#[tokio::main]
+async fn main() {
+    let targets_to_filter: EventFilters = Regex::new("reload_filters").unwrap().into();
+    let (slack_layer, background_worker) = SlackLayer::builder("test-app".to_string(), targets_to_filter)
+        .level_filter(Level::INFO)
+        .build();
+    let filters = slack_layer.filter_handle();
+    let subscriber = Registry::default().with(slack_layer);
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+    handler().await;
+
+    filters.set_level_filter(Some(LevelFilter::DEBUG));
+    debug!("this is the other message you should see");
+    background_worker.shutdown().await;
+}
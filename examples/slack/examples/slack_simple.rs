@@ -1,4 +1,3 @@
-use regex::Regex;
 use tracing::{info, instrument, warn};
 use tracing_subscriber::{layer::SubscriberExt, Registry};
 
@@ -23,7 +22,7 @@ pub async fn controller() {
 #[tokio::main]
 async fn main() {
     // Only show events from where this example code is the target.
-    let target_to_filter: EventFilters = Regex::new("simple").unwrap().into();
+    let target_to_filter = EventFilters::from_patterns(&["simple"], &[]).expect("valid target pattern");
 
     let (slack_layer, background_worker) = SlackLayer::builder("test-app".to_string(), target_to_filter).build();
     let subscriber = Registry::default().with(slack_layer);
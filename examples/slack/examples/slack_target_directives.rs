@@ -0,0 +1,36 @@
+use regex::Regex;
+use tracing::{info, instrument, warn};
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, Registry};
+
+use tracing_layer_slack::{EventFilters, SlackLayer};
+
+mod payments {
+    use tracing::{info, instrument};
+
+    #[instrument]
+    pub async fn charge(amount: u64) {
+        info!(amount, "payment captured");
+    }
+}
+
+#[instrument]
+pub async fn handler() {
+    info!("this should be excluded");
+    warn!("this is one of the messages you should see");
+    payments::charge(42).await;
+}
+
+#[tokio::main]
+async fn main() {
+    let targets_to_filter: EventFilters = Regex::new("target_directives").unwrap().into();
+    let directives: Targets = "slack_target_directives=warn,slack_target_directives::payments=info"
+        .parse()
+        .expect("valid target directives");
+    let (slack_layer, background_worker) = SlackLayer::builder("test-app".to_string(), targets_to_filter)
+        .target_directives(directives)
+        .build();
+    let subscriber = Registry::default().with(slack_layer);
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+    handler().await;
+    background_worker.shutdown().await;
+}
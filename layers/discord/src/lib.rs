@@ -1,11 +1,28 @@
 #![doc = include_str!("../README.md")]
 
-pub use tracing_layer_core::BackgroundWorker;
-pub use tracing_layer_core::layer::WebhookLayer;
-pub use tracing_layer_core::filters::EventFilters;
 use serde::Serialize;
 use serde_json::Value;
+#[cfg(feature = "signal")]
+pub use tracing_layer_core::drain_on_signal;
+pub use tracing_layer_core::fallback::FallbackWriter;
+pub use tracing_layer_core::filters::EventFilters;
 use tracing_layer_core::layer::WebhookLayerBuilder;
+pub use tracing_layer_core::layer::{FilterHandle, LayerSwitch, WebhookLayer};
+pub use tracing_layer_core::retry::RetryPolicy;
+#[cfg(feature = "async-std")]
+pub use tracing_layer_core::runtime::AsyncStdRuntime;
+#[cfg(feature = "smol")]
+pub use tracing_layer_core::runtime::SmolRuntime;
+#[cfg(feature = "tokio")]
+pub use tracing_layer_core::runtime::TokioRuntime;
+pub use tracing_layer_core::runtime::{Runtime, RuntimeFuture, ThreadRuntime};
+pub use tracing_layer_core::scrub::Scrubber;
+#[cfg(feature = "ureq")]
+pub use tracing_layer_core::sink::BlockingWebhookSink;
+#[cfg(feature = "tokio")]
+pub use tracing_layer_core::sink::WebhookSink;
+pub use tracing_layer_core::sink::{Delivered, DeliveryError, DeliveryFuture, Sink};
+pub use tracing_layer_core::{install_panic_hook, BackgroundWorker, DeliveryReport, WorkerGuard, WorkerMetrics};
 use tracing_layer_core::{Config, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
 
 pub struct DiscordLayer;
@@ -16,8 +33,8 @@ impl DiscordLayer {
     }
 }
 
-impl WebhookMessageFactory for DiscordLayer {
-    fn create(inputs: WebhookMessageInputs) -> impl WebhookMessage {
+impl WebhookMessageFactory<DiscordConfig> for DiscordLayer {
+    fn create(_config: &DiscordConfig, inputs: WebhookMessageInputs) -> impl WebhookMessage + 'static {
         let target = inputs.target;
         let span = inputs.span;
         let metadata = inputs.metadata;
@@ -132,9 +149,6 @@ impl WebhookMessageFactory for DiscordLayer {
         }
         #[cfg(not(feature = "embed"))]
         {
-            let event_level = event.metadata().level().as_str();
-            let source_file = event.metadata().file().unwrap_or("Unknown");
-            let source_line = event.metadata().line().unwrap_or(0);
             let payload = format!(
                 concat!(
                 "*Trace from {}*\n",
@@ -147,7 +161,7 @@ impl WebhookMessageFactory for DiscordLayer {
                 "```\n",
                 "*Source*: _{}#L{}_",
                 ),
-                app_name, event_level, message, span, target, metadata, source_file, source_line,
+                app_name, event_level, message, target, span, metadata, source_file, source_line,
             );
             DiscordMessagePayload {
                 content: Some(payload),
@@ -172,7 +186,7 @@ impl DiscordConfig {
     /// available in the environment.
     ///
     /// Required env vars:
-    ///   * SLACK_WEBHOOK_URL
+    ///   * DISCORD_WEBHOOK_URL
     pub fn new_from_env() -> Self {
         Self::new(std::env::var("DISCORD_WEBHOOK_URL").expect("discord webhook url in env"))
     }
@@ -0,0 +1,238 @@
+#![doc = include_str!("../README.md")]
+
+use std::collections::HashMap;
+#[cfg(feature = "signal")]
+pub use tracing_layer_core::drain_on_signal;
+pub use tracing_layer_core::fallback::FallbackWriter;
+pub use tracing_layer_core::filters::EventFilters;
+pub use tracing_layer_core::layer::{FilterHandle, LayerSwitch, WebhookLayer};
+pub use tracing_layer_core::retry::RetryPolicy;
+#[cfg(feature = "async-std")]
+pub use tracing_layer_core::runtime::AsyncStdRuntime;
+#[cfg(feature = "smol")]
+pub use tracing_layer_core::runtime::SmolRuntime;
+#[cfg(feature = "tokio")]
+pub use tracing_layer_core::runtime::TokioRuntime;
+pub use tracing_layer_core::runtime::{Runtime, RuntimeFuture, ThreadRuntime};
+pub use tracing_layer_core::scrub::Scrubber;
+#[cfg(feature = "ureq")]
+pub use tracing_layer_core::sink::BlockingWebhookSink;
+#[cfg(feature = "tokio")]
+pub use tracing_layer_core::sink::WebhookSink;
+pub use tracing_layer_core::sink::{Delivered, DeliveryError, DeliveryFuture, Sink};
+pub use tracing_layer_core::{install_panic_hook, BackgroundWorker, DeliveryReport, WorkerGuard, WorkerMetrics};
+
+use serde::Serialize;
+use serde_json::Value;
+use tracing::Level;
+use tracing_layer_core::layer::WebhookLayerBuilder;
+use tracing_layer_core::{Config, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
+
+/// The PagerDuty Events API v2 endpoint which alerts are sent to.
+const EVENTS_API_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+/// Maximum number of characters allowed in the summary of a PagerDuty alert.
+const MAX_SUMMARY_CHARS: usize = 1024;
+
+/// Layer for triggering PagerDuty alerts from tracing events.
+///
+/// This layer is typically restricted to ERROR events using `level_filter(Level::ERROR)` on its
+/// builder, and installed alongside a chat layer which receives the lower severity events.
+pub struct PagerDutyLayer;
+
+impl PagerDutyLayer {
+    pub fn builder(app_name: String, target_filters: EventFilters) -> WebhookLayerBuilder<PagerDutyConfig, Self> {
+        WebhookLayer::builder(app_name, target_filters)
+    }
+}
+
+impl WebhookMessageFactory<PagerDutyConfig> for PagerDutyLayer {
+    fn create(config: &PagerDutyConfig, inputs: WebhookMessageInputs) -> impl WebhookMessage + 'static {
+        let summary = inputs.message.chars().take(MAX_SUMMARY_CHARS).collect::<String>();
+        let mut custom_details = inputs.rendered_fields;
+        custom_details.remove("message");
+        custom_details.insert(
+            "source".to_string(),
+            Value::String(format!("{}#L{}", inputs.source_file, inputs.source_line)),
+        );
+        PagerDutyMessagePayload {
+            routing_key: config.routing_key.clone(),
+            event_action: "trigger",
+            payload: PagerDutyAlert {
+                summary,
+                source: inputs.app_name,
+                severity: config.severity_for(&inputs.event_level),
+                component: inputs.target,
+                group: inputs.span,
+                custom_details,
+            },
+            webhook_url: inputs.webhook_url,
+        }
+    }
+}
+
+/// The severity of a PagerDuty alert.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Critical,
+    Error,
+    Warning,
+    Info,
+}
+
+/// Configuration describing how to trigger PagerDuty alerts from tracing events.
+pub struct PagerDutyConfig {
+    pub(crate) webhook_url: String,
+    pub(crate) routing_key: String,
+    pub(crate) severities: HashMap<Level, Severity>,
+}
+
+impl PagerDutyConfig {
+    /// Create a config using the integration key of a PagerDuty service's Events API v2 integration.
+    pub fn new(routing_key: String) -> Self {
+        Self {
+            webhook_url: EVENTS_API_URL.to_string(),
+            routing_key,
+            severities: HashMap::new(),
+        }
+    }
+
+    /// Create a new config for triggering PagerDuty alerts using configuration
+    /// available in the environment.
+    ///
+    /// Required env vars:
+    ///   * PAGERDUTY_ROUTING_KEY
+    pub fn new_from_env() -> Self {
+        Self::new(std::env::var("PAGERDUTY_ROUTING_KEY").expect("pagerduty routing key in env"))
+    }
+
+    /// Trigger alerts for events of the given level with a specific severity.
+    ///
+    /// By default, ERROR events trigger `error` alerts, WARN events trigger `warning` alerts, and all
+    /// other events trigger `info` alerts.
+    pub fn severity(mut self, level: Level, severity: Severity) -> Self {
+        self.severities.insert(level, severity);
+        self
+    }
+
+    fn severity_for(&self, level: &Level) -> Severity {
+        if let Some(severity) = self.severities.get(level) {
+            return *severity;
+        }
+        match *level {
+            Level::ERROR => Severity::Error,
+            Level::WARN => Severity::Warning,
+            _ => Severity::Info,
+        }
+    }
+}
+
+impl Default for PagerDutyConfig {
+    fn default() -> Self {
+        Self::new_from_env()
+    }
+}
+
+impl Config for PagerDutyConfig {
+    fn webhook_url(&self) -> &str {
+        &self.webhook_url
+    }
+
+    fn new_from_env() -> Self
+    where
+        Self: Sized,
+    {
+        Self::new_from_env()
+    }
+}
+
+/// The event sent to the PagerDuty Events API. The logged record being "drained" will be
+/// converted into this format.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct PagerDutyMessagePayload {
+    routing_key: String,
+    event_action: &'static str,
+    payload: PagerDutyAlert,
+    #[serde(skip_serializing)]
+    webhook_url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct PagerDutyAlert {
+    summary: String,
+    source: String,
+    severity: Severity,
+    component: String,
+    group: String,
+    custom_details: HashMap<String, Value>,
+}
+
+impl WebhookMessage for PagerDutyMessagePayload {
+    fn webhook_url(&self) -> &str {
+        self.webhook_url.as_str()
+    }
+
+    fn serialize(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize pagerduty event")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(message: String, event_level: Level) -> WebhookMessageInputs {
+        WebhookMessageInputs {
+            app_name: "app".to_string(),
+            message,
+            target: "app::billing".to_string(),
+            span: "charge".to_string(),
+            metadata: "{}".to_string(),
+            fields: HashMap::new(),
+            rendered_fields: HashMap::new(),
+            reserved_fields: HashMap::new(),
+            webhook_url: EVENTS_API_URL.to_string(),
+            source_line: 1,
+            source_file: "src/main.rs".to_string(),
+            event_level,
+            thread: None,
+            span_trace: None,
+            backtrace: None,
+        }
+    }
+
+    fn alert(config: &PagerDutyConfig, inputs: WebhookMessageInputs) -> Value {
+        serde_json::from_str(&PagerDutyLayer::create(config, inputs).serialize()).unwrap()
+    }
+
+    #[test]
+    fn severity_mapping() {
+        let config = PagerDutyConfig::new("key".to_string());
+        assert_eq!(config.severity_for(&Level::ERROR), Severity::Error);
+        assert_eq!(config.severity_for(&Level::WARN), Severity::Warning);
+        assert_eq!(config.severity_for(&Level::INFO), Severity::Info);
+        assert_eq!(config.severity_for(&Level::TRACE), Severity::Info);
+
+        let config = config.severity(Level::ERROR, Severity::Critical);
+        assert_eq!(config.severity_for(&Level::ERROR), Severity::Critical);
+        assert_eq!(config.severity_for(&Level::WARN), Severity::Warning);
+        let alert = alert(&config, inputs("payment failed".to_string(), Level::ERROR));
+        assert_eq!(alert["routing_key"], "key");
+        assert_eq!(alert["event_action"], "trigger");
+        assert_eq!(alert["payload"]["severity"], "critical");
+        assert_eq!(alert["payload"]["component"], "app::billing");
+        assert_eq!(alert["payload"]["custom_details"]["source"], "src/main.rs#L1");
+    }
+
+    #[test]
+    fn long_summaries_are_truncated() {
+        let config = PagerDutyConfig::new("key".to_string());
+        let long = alert(&config, inputs("é".repeat(MAX_SUMMARY_CHARS + 10), Level::ERROR));
+        let summary = long["payload"]["summary"].as_str().unwrap();
+        assert_eq!(summary.chars().count(), MAX_SUMMARY_CHARS);
+
+        let short = alert(&config, inputs("payment failed".to_string(), Level::ERROR));
+        assert_eq!(short["payload"]["summary"], "payment failed");
+    }
+}
@@ -0,0 +1,86 @@
+use std::env::VarError;
+use std::fmt::{Display, Formatter};
+
+/// The environment variables configuring a layer, whose names may share a prefix.
+pub(crate) struct EnvVars<'a> {
+    prefix: &'a str,
+}
+
+impl<'a> EnvVars<'a> {
+    pub(crate) fn new(prefix: &'a str) -> Self {
+        Self { prefix }
+    }
+
+    /// The full name of a variable, e.g. `BILLING_SLACK_CHANNEL` for `SLACK_CHANNEL`.
+    pub(crate) fn name(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    /// The value of a variable, if it is set.
+    pub(crate) fn optional(&self, name: &str) -> Result<Option<String>, ConfigError> {
+        match std::env::var(self.name(name)) {
+            Ok(value) => Ok(Some(value)),
+            Err(VarError::NotPresent) => Ok(None),
+            Err(VarError::NotUnicode(_)) => Err(ConfigError::Invalid {
+                variable: self.name(name),
+                reason: "not valid unicode".to_string(),
+            }),
+        }
+    }
+
+    /// The value of a variable which must be set.
+    pub(crate) fn required(&self, name: &str) -> Result<String, ConfigError> {
+        self.optional(name)?
+            .ok_or_else(|| ConfigError::Missing(self.name(name)))
+    }
+
+    /// The value of a boolean variable, which is `true` or `1`, or `false` or `0`.
+    pub(crate) fn flag(&self, name: &str) -> Result<bool, ConfigError> {
+        match self.optional(name)?.as_deref() {
+            None | Some("false") | Some("0") => Ok(false),
+            Some("true") | Some("1") => Ok(true),
+            Some(value) => Err(ConfigError::Invalid {
+                variable: self.name(name),
+                reason: format!("expected true, false, 1, or 0, found {:?}", value),
+            }),
+        }
+    }
+
+    /// The value of a variable holding an `http` or `https` URL.
+    pub(crate) fn url(&self, name: &str) -> Result<String, ConfigError> {
+        let url = self.required(name)?;
+        match url::Url::parse(&url) {
+            Ok(parsed) if parsed.scheme() == "https" || parsed.scheme() == "http" => Ok(url),
+            Ok(parsed) => Err(ConfigError::Invalid {
+                variable: self.name(name),
+                reason: format!("unsupported URL scheme {:?}", parsed.scheme()),
+            }),
+            Err(e) => Err(ConfigError::Invalid {
+                variable: self.name(name),
+                reason: format!("invalid URL: {}", e),
+            }),
+        }
+    }
+}
+
+/// The reason a configuration could not be read from the environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// A required variable is not set.
+    Missing(String),
+    /// A variable is set to a malformed value.
+    Invalid { variable: String, reason: String },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Missing(variable) => write!(f, "missing environment variable {}", variable),
+            ConfigError::Invalid { variable, reason } => {
+                write!(f, "invalid environment variable {}: {}", variable, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+use tracing::level_filters::LevelFilter;
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+
+use crate::{FilterHandle, SlackConfig};
+
+/// How often a watched configuration file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The options of a [`SlackConfig`] which can be set in a TOML, YAML, or JSON file.
+///
+/// Every option is optional, and overrides the option set in code. Options which are not set in the
+/// file keep the value set in code.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    webhook_url: Option<String>,
+    /// Post with the Web API, authenticated by this bot token, instead of a webhook.
+    bot_token: Option<String>,
+    additional_webhook_urls: Vec<String>,
+    channel: Option<String>,
+    /// Channels by level name, e.g. `error = "#alerts"`.
+    level_channels: HashMap<String, String>,
+    username: Option<String>,
+    icon_emoji: Option<String>,
+    icon_url: Option<String>,
+    mention: Option<String>,
+    /// The least severe level mentioned, ERROR by default.
+    mention_level: Option<String>,
+    template: Option<String>,
+    startup_message: Option<String>,
+    shutdown_message: Option<String>,
+    /// Only post events at this level or above.
+    level: Option<String>,
+    /// Only post events enabled by these `RUST_LOG`-style directives.
+    targets: Option<String>,
+    rate_limit: Option<RateLimit>,
+    /// Send requests to Slack through this HTTP or HTTPS proxy.
+    proxy: Option<String>,
+    connect_timeout_secs: Option<u64>,
+    request_timeout_secs: Option<u64>,
+    pool_idle_timeout_secs: Option<u64>,
+    pool_max_idle_per_host: Option<usize>,
+    tcp_keepalive_secs: Option<u64>,
+}
+
+/// The maximum number of messages delivered per interval.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RateLimit {
+    max_messages: u32,
+    interval_secs: u64,
+}
+
+impl ConfigFile {
+    /// Read and parse a configuration file, in the format given by its extension.
+    pub(crate) fn load(path: &Path) -> Result<Self, ConfigFileError> {
+        let contents = std::fs::read_to_string(path).map_err(ConfigFileError::Io)?;
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        match extension {
+            "toml" => toml::from_str(&contents).map_err(|e| ConfigFileError::Parse(e.to_string())),
+            "yaml" | "yml" => serde_yaml::from_str(&contents).map_err(|e| ConfigFileError::Parse(e.to_string())),
+            "json" => serde_json::from_str(&contents).map_err(|e| ConfigFileError::Parse(e.to_string())),
+            _ => Err(ConfigFileError::UnsupportedFormat(path.to_path_buf())),
+        }
+    }
+
+    /// Create a configuration from the options set in the file, which must include a webhook URL or a
+    /// bot token.
+    pub(crate) fn into_config(self) -> Result<SlackConfig, ConfigFileError> {
+        if self.webhook_url.is_none() && self.bot_token.is_none() {
+            return Err(ConfigFileError::Invalid(
+                "webhook_url or bot_token is required".to_string(),
+            ));
+        }
+        self.apply(SlackConfig::new(String::new()))
+    }
+
+    /// Apply the options set in the file to a configuration.
+    pub(crate) fn apply(self, mut config: SlackConfig) -> Result<SlackConfig, ConfigFileError> {
+        let level =
+            |level: &str| Level::from_str(level).map_err(|_| ConfigFileError::Invalid(format!("level {:?}", level)));
+        if let Some(webhook_url) = self.webhook_url {
+            config = config.webhook_url(webhook_url);
+        }
+        #[cfg(feature = "tokio")]
+        if let Some(bot_token) = self.bot_token {
+            config = config.web_api(bot_token);
+        }
+        #[cfg(not(feature = "tokio"))]
+        if self.bot_token.is_some() {
+            return Err(ConfigFileError::Invalid(
+                "bot_token requires the `tokio` feature".to_string(),
+            ));
+        }
+        for webhook_url in self.additional_webhook_urls {
+            config = config.additional_webhook_url(webhook_url);
+        }
+        if let Some(channel) = self.channel {
+            config = config.channel(channel);
+        }
+        for (level_name, channel) in self.level_channels {
+            config = config.level_channel(level(&level_name)?, channel);
+        }
+        if let Some(username) = self.username {
+            config = config.username(username);
+        }
+        if let Some(icon_emoji) = self.icon_emoji {
+            config = config.icon_emoji(icon_emoji);
+        }
+        if let Some(icon_url) = self.icon_url {
+            config = config.icon_url(icon_url);
+        }
+        if let Some(mention) = self.mention {
+            let mention_level = self.mention_level.as_deref().map(level).transpose()?;
+            config = config.mention(mention_level.unwrap_or(Level::ERROR), mention);
+        }
+        if let Some(template) = self.template {
+            #[cfg(feature = "templates")]
+            {
+                config = config
+                    .template(template)
+                    .map_err(|e| ConfigFileError::Invalid(format!("template: {}", e)))?;
+            }
+            #[cfg(not(feature = "templates"))]
+            {
+                let _ = template;
+                return Err(ConfigFileError::Invalid(
+                    "template requires the templates feature".to_string(),
+                ));
+            }
+        }
+        if let Some(startup_message) = self.startup_message {
+            config = config.startup_message(startup_message);
+        }
+        if let Some(shutdown_message) = self.shutdown_message {
+            config = config.shutdown_message(shutdown_message);
+        }
+        if let Some(level_filter) = self.level {
+            let level_filter = LevelFilter::from_str(&level_filter)
+                .map_err(|_| ConfigFileError::Invalid(format!("level {:?}", level_filter)))?;
+            config.level_filter = Some(level_filter);
+        }
+        if let Some(targets) = self.targets {
+            let targets =
+                Targets::from_str(&targets).map_err(|e| ConfigFileError::Invalid(format!("targets: {}", e)))?;
+            config.target_directives = Some(targets);
+        }
+        if let Some(proxy) = self.proxy {
+            config = config.proxy(proxy);
+        }
+        if let Some(timeout) = self.connect_timeout_secs {
+            config = config.connect_timeout(Duration::from_secs(timeout));
+        }
+        if let Some(timeout) = self.request_timeout_secs {
+            config = config.request_timeout(Duration::from_secs(timeout));
+        }
+        if let Some(timeout) = self.pool_idle_timeout_secs {
+            config = config.pool_idle_timeout(Some(Duration::from_secs(timeout)));
+        }
+        if let Some(max_idle) = self.pool_max_idle_per_host {
+            config = config.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(interval) = self.tcp_keepalive_secs {
+            config = config.tcp_keepalive(Some(Duration::from_secs(interval)));
+        }
+        if let Some(rate_limit) = self.rate_limit {
+            config = config.rate_limit(rate_limit.max_messages, Duration::from_secs(rate_limit.interval_secs));
+        }
+        Ok(config)
+    }
+}
+
+/// A configuration file which is reloaded whenever it changes.
+pub(crate) struct ConfigWatch {
+    path: PathBuf,
+    /// The configuration set in code, which the file's options are applied to.
+    base: SlackConfig,
+    current: RwLock<Arc<SlackConfig>>,
+    /// The filter handles of the layers using the configuration, whose configuration filters are replaced
+    /// by the file's `level` and `targets` when it is reloaded.
+    filters: Mutex<Vec<FilterHandle>>,
+}
+
+impl ConfigWatch {
+    /// Load a configuration file, and watch it for changes on a background thread for as long as the
+    /// configuration is in use.
+    pub(crate) fn start(path: PathBuf, base: SlackConfig) -> Result<Arc<Self>, ConfigFileError> {
+        let current = ConfigFile::load(&path)?.apply(base.clone())?;
+        let watch = Arc::new(Self {
+            path,
+            base,
+            current: RwLock::new(Arc::new(current)),
+            filters: Mutex::new(Vec::new()),
+        });
+        let weak = Arc::downgrade(&watch);
+        let modified = watch.modified();
+        std::thread::Builder::new()
+            .name("slack-config-watch".to_string())
+            .spawn(move || Self::watch(weak, modified))
+            .map_err(ConfigFileError::Io)?;
+        Ok(watch)
+    }
+
+    /// The configuration with the file's latest valid options applied.
+    pub(crate) fn current(&self) -> Arc<SlackConfig> {
+        match self.current.read() {
+            Ok(current) => current.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Replace the configuration filters of a layer whenever the file is reloaded.
+    pub(crate) fn attach(&self, filters: FilterHandle) {
+        if let Ok(mut handles) = self.filters.lock() {
+            handles.push(filters);
+        }
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    fn watch(watch: Weak<Self>, mut modified: Option<SystemTime>) {
+        loop {
+            std::thread::sleep(WATCH_INTERVAL);
+            let watch = match watch.upgrade() {
+                Some(watch) => watch,
+                None => return,
+            };
+            let last_modified = watch.modified();
+            if last_modified == modified {
+                continue;
+            }
+            modified = last_modified;
+            // An invalid file keeps the previous configuration in use, so a typo does not stop messages.
+            match ConfigFile::load(&watch.path).and_then(|file| file.apply(watch.base.clone())) {
+                Ok(config) => {
+                    if let Ok(handles) = watch.filters.lock() {
+                        for filters in handles.iter() {
+                            filters.set_config_filters(config.level_filter, config.target_directives.clone());
+                        }
+                    }
+                    if let Ok(mut current) = watch.current.write() {
+                        *current = Arc::new(config);
+                    }
+                }
+                Err(e) => println!(
+                    "ERROR: failed to reload slack config from {}: {}",
+                    watch.path.display(),
+                    e
+                ),
+            }
+        }
+    }
+}
+
+/// The reason a configuration file could not be loaded.
+#[derive(Debug)]
+pub enum ConfigFileError {
+    /// The file could not be read.
+    Io(std::io::Error),
+    /// The file's extension is not `.toml`, `.yaml`, `.yml`, or `.json`.
+    UnsupportedFormat(PathBuf),
+    /// The file is not valid TOML, YAML, or JSON, or has unknown options.
+    Parse(String),
+    /// An option has an invalid value.
+    Invalid(String),
+}
+
+impl Display for ConfigFileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigFileError::Io(e) => write!(f, "failed to read config file: {}", e),
+            ConfigFileError::UnsupportedFormat(path) => write!(f, "unsupported config file format: {}", path.display()),
+            ConfigFileError::Parse(e) => write!(f, "failed to parse config file: {}", e),
+            ConfigFileError::Invalid(option) => write!(f, "invalid config option: {}", option),
+        }
+    }
+}
+
+impl std::error::Error for ConfigFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigFileError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use serde_json::Value;
+use tracing_layer_core::WebhookMessageInputs;
+
+/// The data available to an [`EventFormatter`] about an event.
+pub type EventData = WebhookMessageInputs;
+
+/// A custom layout for the messages sent to Slack, replacing the default layout entirely.
+///
+/// The message is still posted with the configured channel, username, and icon. Closures taking an
+/// [`EventData`] and returning a [`SlackMessage`] can be used as formatters.
+pub trait EventFormatter: Send + Sync {
+    fn format(&self, event: &EventData) -> SlackMessage;
+}
+
+impl<F> EventFormatter for F
+where
+    F: Fn(&EventData) -> SlackMessage + Send + Sync,
+{
+    fn format(&self, event: &EventData) -> SlackMessage {
+        self(event)
+    }
+}
+
+/// The content of a Slack message produced by an [`EventFormatter`].
+#[derive(Debug, Clone, Default)]
+pub struct SlackMessage {
+    /// The text of the message, or its fallback text for notifications if it has blocks.
+    pub text: Option<String>,
+    /// The [Block Kit](https://api.slack.com/block-kit) blocks of the message, as a JSON array.
+    pub blocks: Option<Value>,
+    /// The legacy attachments of the message, as a JSON array.
+    pub attachments: Option<Value>,
+}
+
+impl SlackMessage {
+    /// A message consisting of mrkdwn text.
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            text: Some(text.into()),
+            ..Self::default()
+        }
+    }
+
+    /// A message consisting of Block Kit blocks.
+    pub fn blocks(blocks: Value) -> Self {
+        Self {
+            blocks: Some(blocks),
+            ..Self::default()
+        }
+    }
+}
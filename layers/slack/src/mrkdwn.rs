@@ -0,0 +1,30 @@
+/// Escape text interpolated into a Slack mrkdwn message, so it is displayed as written.
+///
+/// Slack requires `&`, `<`, and `>` to be escaped as HTML entities, as they otherwise create links and
+/// mentions. Backticks are followed by a zero-width space, so they cannot open or close code blocks.
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '`' => escaped.push_str("`\u{200b}"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape every string within a JSON value.
+#[cfg(feature = "templates")]
+pub(crate) fn escape_value(value: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+
+    match value {
+        Value::String(s) => Value::String(escape(&s)),
+        Value::Array(values) => Value::Array(values.into_iter().map(escape_value).collect()),
+        Value::Object(map) => Value::Object(map.into_iter().map(|(k, v)| (k, escape_value(v))).collect()),
+        value => value,
+    }
+}
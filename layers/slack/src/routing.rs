@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde_json::Value;
+use tracing::Level;
+
+/// A rule routing matching events to a Slack channel.
+///
+/// A rule matches an event when all of its conditions are met. A rule without conditions matches
+/// every event. For example, to send errors from the billing module about enterprise customers to
+/// their own channel:
+///
+/// ```ignore
+/// RoutingRule::new("#vip-alerts")
+///     .min_level(Level::ERROR)
+///     .target(Regex::new("^my_app::billing").unwrap())
+///     .field("customer_tier", Regex::new("^enterprise$").unwrap())
+/// ```
+#[derive(Debug, Clone)]
+pub struct RoutingRule {
+    channel: String,
+    min_level: Option<Level>,
+    target: Option<Regex>,
+    fields: Vec<(String, Regex)>,
+}
+
+impl RoutingRule {
+    /// Create a rule routing events to the given channel.
+    pub fn new(channel: impl Into<String>) -> Self {
+        Self {
+            channel: channel.into(),
+            min_level: None,
+            target: None,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Only match events at the given level or above.
+    pub fn min_level(mut self, level: Level) -> Self {
+        self.min_level = Some(level);
+        self
+    }
+
+    /// Only match events whose target matches the regex.
+    pub fn target(mut self, target: Regex) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// Only match events which have the field, on the event or its current span, with a value matching
+    /// the regex. Non-string values are matched against their JSON representation.
+    pub fn field(mut self, name: impl Into<String>, value: Regex) -> Self {
+        self.fields.push((name.into(), value));
+        self
+    }
+
+    /// The channel matching events are routed to.
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    pub(crate) fn matches(&self, level: &Level, target: &str, fields: &HashMap<String, Value>) -> bool {
+        if let Some(min_level) = &self.min_level {
+            if level > min_level {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.target {
+            if !pattern.is_match(target) {
+                return false;
+            }
+        }
+        self.fields.iter().all(|(name, pattern)| match fields.get(name) {
+            Some(Value::String(value)) => pattern.is_match(value),
+            Some(value) => pattern.is_match(&value.to_string()),
+            None => false,
+        })
+    }
+}
@@ -0,0 +1,67 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A daily period during which events below ERROR are not posted normally, so overnight noise does not
+/// notify anyone. Events within quiet hours are dropped, unless they are redirected to another channel.
+///
+/// For example, to redirect events from 22:00 until 07:00 in UTC-5 to a low-priority channel:
+///
+/// ```ignore
+/// QuietHours::new((22, 0), (7, 0))
+///     .utc_offset(-5 * 60)
+///     .channel("#alerts-overnight")
+/// ```
+#[derive(Debug, Clone)]
+pub struct QuietHours {
+    /// The minute of the day quiet hours start at.
+    start: u32,
+    /// The minute of the day quiet hours end at.
+    end: u32,
+    /// The offset of the time zone from UTC, in minutes.
+    utc_offset: i32,
+    channel: Option<String>,
+}
+
+impl QuietHours {
+    /// Quiet hours from `start` until `end`, given as the hour and minute of the day in UTC. Quiet hours
+    /// which end earlier in the day than they start span midnight.
+    pub fn new(start: (u32, u32), end: (u32, u32)) -> Self {
+        let minute_of_day = |(hour, minute): (u32, u32)| (hour * 60 + minute) % (24 * 60);
+        Self {
+            start: minute_of_day(start),
+            end: minute_of_day(end),
+            utc_offset: 0,
+            channel: None,
+        }
+    }
+
+    /// Interpret the start and end in the time zone with the given offset from UTC, in minutes (e.g.
+    /// `-5 * 60` for UTC-5). The offset is fixed, so it does not follow daylight saving time.
+    pub fn utc_offset(mut self, minutes: i32) -> Self {
+        self.utc_offset = minutes;
+        self
+    }
+
+    /// Post the events within quiet hours to the given channel, instead of dropping them.
+    pub fn channel(mut self, channel: impl Into<String>) -> Self {
+        self.channel = Some(channel.into());
+        self
+    }
+
+    /// The channel events within quiet hours are redirected to, if they are not dropped.
+    pub fn redirect_channel(&self) -> Option<&str> {
+        self.channel.as_deref()
+    }
+
+    /// Whether the given time is within quiet hours.
+    pub fn contains(&self, time: SystemTime) -> bool {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as i64);
+        let minute = (secs / 60 + self.utc_offset as i64).rem_euclid(24 * 60) as u32;
+        if self.start <= self.end {
+            self.start <= minute && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
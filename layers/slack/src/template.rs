@@ -0,0 +1,55 @@
+/// Replace every `{name}` placeholder in a template with the value looked up for `name`.
+///
+/// Placeholders without a value are kept as they are, and `{{` and `}}` produce literal braces.
+pub(crate) fn render(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            rendered.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+        let placeholder = match rest.find('}') {
+            Some(end) if rest.starts_with('{') => &rest[..=end],
+            _ => {
+                rendered.push_str(&rest[..1]);
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        match lookup(&placeholder[1..placeholder.len() - 1]) {
+            Some(value) => rendered.push_str(&value),
+            None => rendered.push_str(placeholder),
+        }
+        rest = &rest[placeholder.len()..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// A template rendering the text of every message, replacing the default layout.
+#[cfg(feature = "templates")]
+#[derive(Clone)]
+pub(crate) struct MessageTemplate {
+    env: minijinja::Environment<'static>,
+}
+
+#[cfg(feature = "templates")]
+impl MessageTemplate {
+    const NAME: &'static str = "message";
+
+    /// Compile a template, reporting syntax errors.
+    pub(crate) fn new(source: String) -> Result<Self, minijinja::Error> {
+        let mut env = minijinja::Environment::new();
+        env.add_template_owned(Self::NAME, source)?;
+        Ok(Self { env })
+    }
+
+    /// Render the template with the given context.
+    pub(crate) fn render(&self, context: &serde_json::Value) -> Result<String, minijinja::Error> {
+        self.env.get_template(Self::NAME)?.render(context)
+    }
+}
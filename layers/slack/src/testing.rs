@@ -0,0 +1,144 @@
+//! Helpers for testing alerting rules without posting to Slack.
+//!
+//! A [`CapturingSink`] records every message instead of sending it, so a test can emit events and assert
+//! which messages would have been posted:
+//!
+//! ```ignore
+//! let sink = CapturingSink::new();
+//! let (slack_layer, background_worker) = SlackLayer::builder("my-app".to_string(), targets)
+//!     .config(SlackConfig::new("https://hooks.slack.com/services/test".to_string()))
+//!     .sink(sink.clone())
+//!     .build();
+//! // ...emit events...
+//! background_worker.flush().await;
+//! sink.assert_message_containing("Payment failed");
+//! assert_eq!(sink.count_for_channel("#payments"), 1);
+//! ```
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde_json::Value;
+use tracing_layer_core::{Delivered, DeliveryFuture, Sink, WebhookMessage};
+
+use crate::SlackMessagePayload;
+
+/// A message recorded by a [`CapturingSink`].
+#[derive(Debug, Clone)]
+pub struct CapturedMessage {
+    webhook_url: String,
+    payload: Value,
+}
+
+impl CapturedMessage {
+    /// The webhook URL, or Web API method URL, the message would have been posted to.
+    pub fn webhook_url(&self) -> &str {
+        &self.webhook_url
+    }
+
+    /// The JSON payload of the message.
+    pub fn payload(&self) -> &Value {
+        &self.payload
+    }
+
+    /// The message as a Slack payload, unless it is not a message payload, e.g. a Web API request.
+    pub fn slack_payload(&self) -> Option<SlackMessagePayload> {
+        let mut payload: SlackMessagePayload = serde_json::from_value(self.payload.clone()).ok()?;
+        payload.webhook_url = self.webhook_url.clone();
+        Some(payload)
+    }
+
+    /// The channel the message would have been posted to, unless it is posted to the webhook's own
+    /// channel.
+    pub fn channel(&self) -> Option<&str> {
+        self.payload.get("channel").and_then(Value::as_str)
+    }
+
+    /// All text of the message, i.e. its fallback text and the text of its blocks and attachments, one
+    /// per line.
+    pub fn text(&self) -> String {
+        let mut text = Vec::new();
+        collect_text(&self.payload, &mut text);
+        text.join("\n")
+    }
+}
+
+fn collect_text<'a>(value: &'a Value, text: &mut Vec<&'a str>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                match value {
+                    Value::String(value) if key == "text" || key == "fallback" || key == "title" => text.push(value),
+                    value => collect_text(value, text),
+                }
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|value| collect_text(value, text)),
+        _ => {}
+    }
+}
+
+/// A sink which records every message in memory instead of sending it.
+///
+/// Clones share the recorded messages, so a clone can be given to the layer builder's `sink` and the
+/// original inspected once the worker was [flushed](crate::BackgroundWorker::flush).
+#[derive(Debug, Clone, Default)]
+pub struct CapturingSink {
+    messages: Arc<Mutex<Vec<CapturedMessage>>>,
+}
+
+impl CapturingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The messages recorded so far, in the order they were delivered.
+    pub fn messages(&self) -> Vec<CapturedMessage> {
+        self.lock().clone()
+    }
+
+    /// Forget the messages recorded so far.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// The number of recorded messages posted to the given channel.
+    pub fn count_for_channel(&self, channel: &str) -> usize {
+        self.lock()
+            .iter()
+            .filter(|message| message.channel() == Some(channel))
+            .count()
+    }
+
+    /// Assert that a recorded message contains the given text, returning the first such message.
+    ///
+    /// # Panics
+    ///
+    /// If no recorded message contains the text, listing the text of every recorded message.
+    #[track_caller]
+    pub fn assert_message_containing(&self, text: &str) -> CapturedMessage {
+        let messages = self.lock();
+        match messages.iter().find(|message| message.text().contains(text)) {
+            Some(message) => message.clone(),
+            None => {
+                let recorded: Vec<String> = messages.iter().map(CapturedMessage::text).collect();
+                panic!("no message contains {:?}, recorded messages: {:#?}", text, recorded)
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<CapturedMessage>> {
+        self.messages.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Sink for CapturingSink {
+    fn deliver<'a>(&'a self, message: &'a dyn WebhookMessage) -> DeliveryFuture<'a> {
+        Box::pin(async move {
+            let payload = serde_json::from_str(&message.serialize()).unwrap_or(Value::Null);
+            self.lock().push(CapturedMessage {
+                webhook_url: message.webhook_url().to_string(),
+                payload,
+            });
+            Ok(Delivered::default())
+        })
+    }
+}
@@ -0,0 +1,127 @@
+use std::fmt::{Display, Formatter};
+
+#[cfg(feature = "tokio")]
+use debug_print::debug_println;
+use tracing_layer_core::DeliveryError;
+
+/// The longest channel name Slack allows.
+const MAX_CHANNEL_NAME_LENGTH: usize = 80;
+
+/// Check that a webhook URL is an absolute `http` or `https` URL.
+pub(crate) fn check_webhook_url(url: &str) -> Result<(), ValidationError> {
+    // Webhook URLs embed their secret, so errors describe the URL without including it.
+    match url::Url::parse(url) {
+        Ok(parsed) if parsed.scheme() != "https" && parsed.scheme() != "http" => Err(
+            ValidationError::InvalidWebhookUrl(format!("unsupported URL scheme {:?}", parsed.scheme())),
+        ),
+        Ok(parsed) if parsed.host_str().is_none() => {
+            Err(ValidationError::InvalidWebhookUrl("the URL has no host".to_string()))
+        }
+        Ok(_) => Ok(()),
+        Err(e) => Err(ValidationError::InvalidWebhookUrl(e.to_string())),
+    }
+}
+
+/// Check that a channel is a `#channel` name, a `@member` name, or a conversation ID (e.g. `C0123ABCD`).
+/// Mattermost channels may also be named without the leading `#`.
+pub(crate) fn check_channel(channel: &str, mattermost: bool) -> Result<(), ValidationError> {
+    let invalid = || ValidationError::InvalidChannel(channel.to_string());
+    let is_id = channel.len() >= 9
+        && channel.starts_with(['C', 'G', 'D'])
+        && channel.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+    if is_id {
+        return Ok(());
+    }
+    let name = match channel.strip_prefix('#').or_else(|| channel.strip_prefix('@')) {
+        Some(name) => name,
+        None if mattermost => channel,
+        None => return Err(invalid()),
+    };
+    let valid_name = !name.is_empty()
+        && name.chars().count() <= MAX_CHANNEL_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| !c.is_whitespace() && !c.is_uppercase() && c != ',' && c != '#');
+    if valid_name {
+        Ok(())
+    } else {
+        Err(invalid())
+    }
+}
+
+/// Check that an incoming webhook exists, without posting a message.
+///
+/// The webhook is sent an empty payload, which Slack and Mattermost reject with `400 Bad Request` if the
+/// webhook exists, and with another status if it was revoked or never existed.
+#[cfg(feature = "tokio")]
+pub(crate) async fn ping_webhook(client: &reqwest::Client, url: &str) -> Result<(), ValidationError> {
+    let res = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body("{}")
+        .send()
+        .await
+        .map_err(|e| ValidationError::Unreachable(DeliveryError::Request(e.without_url())))?;
+    let status = res.status();
+    let body = res.text().await.unwrap_or_default();
+    debug_println!("webhook ping response: {} {}", status, body);
+    if status.is_success() || status == reqwest::StatusCode::BAD_REQUEST {
+        return Ok(());
+    }
+    Err(ValidationError::Unreachable(DeliveryError::Status {
+        status: status.as_u16(),
+        body,
+    }))
+}
+
+/// Check that a proxy URL is valid, as the HTTP client would parse it.
+pub(crate) fn is_valid_proxy(proxy: &str) -> bool {
+    #[cfg(feature = "tokio")]
+    return reqwest::Proxy::all(proxy).is_ok();
+    // Like reqwest, URLs without a scheme are HTTP proxies.
+    #[cfg(not(feature = "tokio"))]
+    return if proxy.contains("://") {
+        url::Url::parse(proxy).is_ok()
+    } else {
+        url::Url::parse(&format!("http://{}", proxy)).is_ok()
+    };
+}
+
+/// The reason a configuration is invalid.
+#[derive(Debug)]
+pub enum ValidationError {
+    /// A webhook URL is malformed.
+    InvalidWebhookUrl(String),
+    /// A channel is not a `#channel` or `@member` name, or a conversation ID.
+    InvalidChannel(String),
+    /// The bot token is not a Slack token (`xoxb-...`).
+    InvalidBotToken,
+    /// The proxy URL is malformed.
+    InvalidProxy,
+    /// A message has no text.
+    EmptyMessage,
+    /// A webhook or the Web API could not be reached, or rejected the configuration's credentials.
+    Unreachable(DeliveryError),
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::InvalidWebhookUrl(reason) => write!(f, "invalid webhook url: {}", reason),
+            ValidationError::InvalidChannel(channel) => write!(f, "invalid channel {:?}", channel),
+            ValidationError::InvalidBotToken => write!(f, "invalid bot token"),
+            ValidationError::InvalidProxy => write!(f, "invalid proxy url"),
+            ValidationError::EmptyMessage => write!(f, "the message has no text"),
+            ValidationError::Unreachable(e) => write!(f, "slack is unreachable: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ValidationError::Unreachable(e) => Some(e),
+            _ => None,
+        }
+    }
+}
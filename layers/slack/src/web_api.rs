@@ -0,0 +1,329 @@
+#[cfg(feature = "tokio")]
+use std::collections::HashMap;
+#[cfg(feature = "tokio")]
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "tokio")]
+use debug_print::debug_println;
+#[cfg(feature = "tokio")]
+use serde::Deserialize;
+#[cfg(feature = "tokio")]
+use serde_json::Value;
+#[cfg(feature = "tokio")]
+use tracing_layer_core::sink::retry_after;
+#[cfg(feature = "tokio")]
+use tracing_layer_core::{Attachment, Delivered, DeliveryError, DeliveryFuture, MessageThread, Sink, WebhookMessage};
+
+/// The Slack Web API method used to post messages.
+#[cfg(feature = "tokio")]
+pub(crate) const POST_MESSAGE_URL: &str = "https://slack.com/api/chat.postMessage";
+
+/// The Slack Web API method used to edit messages.
+pub(crate) const UPDATE_MESSAGE_URL: &str = "https://slack.com/api/chat.update";
+
+/// The Slack Web API methods used to upload files.
+#[cfg(feature = "tokio")]
+const RESERVE_UPLOAD_URL: &str = "https://slack.com/api/files.getUploadURLExternal";
+#[cfg(feature = "tokio")]
+const COMPLETE_UPLOAD_URL: &str = "https://slack.com/api/files.completeUploadExternal";
+
+/// The Slack Web API method used to check a bot token.
+#[cfg(feature = "tokio")]
+const AUTH_TEST_URL: &str = "https://slack.com/api/auth.test";
+
+/// The Slack Web API method used to find members by their email address.
+#[cfg(feature = "tokio")]
+const LOOKUP_BY_EMAIL_URL: &str = "https://slack.com/api/users.lookupByEmail";
+
+/// The prefix of placeholder mentions which name a member by their email address, and are resolved
+/// into a mention of the member's ID before a message is posted.
+pub(crate) const EMAIL_MENTION_PREFIX: &str = "<@email:";
+
+/// A sink posting messages with the Slack Web API's `chat.postMessage` method, authenticated by a bot
+/// token, instead of an incoming webhook.
+///
+/// Unlike webhooks, the Web API requires every message to name its channel, which the bot must be a
+/// member of. Messages belonging to a [thread](MessageThread) are posted as replies to the thread's
+/// first message in the same channel, and the span's summary is appended to that first message once the
+/// span closes. Messages with an attachment have it uploaded as a file in their thread.
+///
+/// Members mentioned by their email address are looked up once, and the result is cached for the
+/// lifetime of the sink.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Debug)]
+pub struct SlackWebApiSink {
+    client: reqwest::Client,
+    bot_token: String,
+    member_ids: Arc<Mutex<HashMap<String, Option<String>>>>,
+}
+
+#[cfg(feature = "tokio")]
+impl SlackWebApiSink {
+    pub fn new(bot_token: impl Into<String>) -> Self {
+        Self::with_client(bot_token, reqwest::Client::new())
+    }
+
+    /// A sink sending requests with the given client, e.g. one configured with a proxy.
+    pub fn with_client(bot_token: impl Into<String>, client: reqwest::Client) -> Self {
+        Self {
+            client,
+            bot_token: bot_token.into(),
+            member_ids: Arc::default(),
+        }
+    }
+}
+
+/// The envelope of every Slack Web API response.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Deserialize)]
+pub(crate) struct SlackApiResponse {
+    pub(crate) ok: bool,
+    #[serde(default)]
+    pub(crate) error: Option<String>,
+    /// The ID of the channel the message was posted to.
+    #[serde(default)]
+    pub(crate) channel: Option<String>,
+    /// The timestamp identifying the posted message.
+    #[serde(default)]
+    pub(crate) ts: Option<String>,
+    /// The URL a reserved file upload should be sent to.
+    #[serde(default)]
+    pub(crate) upload_url: Option<String>,
+    /// The HTTP status of the response.
+    #[serde(skip)]
+    pub(crate) status: u16,
+    /// The ID of a reserved file upload.
+    #[serde(default)]
+    pub(crate) file_id: Option<String>,
+    /// The member which was looked up.
+    #[serde(default)]
+    pub(crate) user: Option<SlackMember>,
+}
+
+/// A Slack workspace member.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Deserialize)]
+pub(crate) struct SlackMember {
+    pub(crate) id: String,
+}
+
+#[cfg(feature = "tokio")]
+impl SlackWebApiSink {
+    /// Call a Web API method with JSON arguments, returning its response if the call succeeded.
+    async fn call(&self, url: &str, payload_json: String) -> Result<SlackApiResponse, DeliveryError> {
+        debug_println!("sending slack api message: {}", &payload_json);
+        let request = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(payload_json);
+        self.send(request).await
+    }
+
+    /// Send an authenticated Web API request, returning its response if the call succeeded.
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<SlackApiResponse, DeliveryError> {
+        let res = request
+            .bearer_auth(&self.bot_token)
+            .send()
+            .await
+            .map_err(|e| DeliveryError::Request(e.without_url()))?;
+        let status = res.status();
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Err(DeliveryError::RateLimited {
+                retry_after: retry_after(res.headers()),
+            });
+        }
+        let res_text = res.text().await.unwrap_or_default();
+        debug_println!("slack api response: {}", res_text);
+        if !status.is_success() {
+            return Err(DeliveryError::Status {
+                status: status.as_u16(),
+                body: res_text,
+            });
+        }
+        // The Web API responds with 200 OK to most failed calls, describing the failure in the body.
+        let mut response: SlackApiResponse = serde_json::from_str(&res_text)
+            .map_err(|e| DeliveryError::Rejected(format!("invalid slack api response: {}", e)))?;
+        response.status = status.as_u16();
+        if !response.ok {
+            return Err(DeliveryError::Rejected(
+                response.error.unwrap_or_else(|| "unknown error".to_string()),
+            ));
+        }
+        Ok(response)
+    }
+
+    /// Check that the bot token is valid, without posting a message.
+    pub(crate) async fn auth_test(&self) -> Result<(), DeliveryError> {
+        self.call(AUTH_TEST_URL, "{}".to_string()).await.map(|_| ())
+    }
+
+    /// Upload a file and share it in a message's thread.
+    async fn upload(&self, attachment: &Attachment, channel_id: &str, thread_ts: &str) -> Result<(), DeliveryError> {
+        // Uploads are reserved with form-encoded arguments, which the method requires.
+        let length = attachment.content.len().to_string();
+        let request = self
+            .client
+            .post(RESERVE_UPLOAD_URL)
+            .form(&[("filename", attachment.filename.as_str()), ("length", length.as_str())]);
+        let reservation = self.send(request).await?;
+        let (upload_url, file_id) = match (reservation.upload_url, reservation.file_id) {
+            (Some(upload_url), Some(file_id)) => (upload_url, file_id),
+            _ => return Err(DeliveryError::Rejected("missing upload url".to_string())),
+        };
+
+        let res = self
+            .client
+            .post(upload_url)
+            .body(attachment.content.clone())
+            .send()
+            .await
+            .map_err(|e| DeliveryError::Request(e.without_url()))?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(DeliveryError::Status {
+                status: status.as_u16(),
+                body: res.text().await.unwrap_or_default(),
+            });
+        }
+
+        let complete = serde_json::json!({
+            "files": [{ "id": file_id, "title": attachment.filename }],
+            "channel_id": channel_id,
+            "thread_ts": thread_ts,
+        });
+        self.call(COMPLETE_UPLOAD_URL, complete.to_string()).await?;
+        Ok(())
+    }
+
+    /// The ID of the member with the given email address, if there is one.
+    async fn member_id(&self, email: &str) -> Option<String> {
+        if let Some(member_id) = self.member_ids.lock().ok()?.get(email) {
+            return member_id.clone();
+        }
+        let request = self.client.get(LOOKUP_BY_EMAIL_URL).query(&[("email", email)]);
+        let member_id = match self.send(request).await {
+            Ok(response) => response.user.map(|user| user.id),
+            // Unknown members are cached as well, but a failed lookup may be retried.
+            Err(DeliveryError::Rejected(_)) => None,
+            Err(e) => {
+                println!("ERROR: failed to look up slack member by email: {}", e);
+                return None;
+            }
+        };
+        if let Ok(mut member_ids) = self.member_ids.lock() {
+            member_ids.insert(email.to_string(), member_id.clone());
+        }
+        member_id
+    }
+
+    /// Replace the placeholder mentions of members by their email address with mentions of their IDs,
+    /// or with the plain email address of members which could not be found.
+    async fn resolve_email_mentions(&self, payload_json: String) -> String {
+        if !payload_json.contains(EMAIL_MENTION_PREFIX) {
+            return payload_json;
+        }
+        let mut resolved = String::with_capacity(payload_json.len());
+        let mut rest = payload_json.as_str();
+        while let Some(start) = rest.find(EMAIL_MENTION_PREFIX) {
+            let end = match rest[start..].find('>') {
+                Some(end) => start + end,
+                None => break,
+            };
+            resolved.push_str(&rest[..start]);
+            let email = &rest[start + EMAIL_MENTION_PREFIX.len()..end];
+            match self.member_id(email).await {
+                Some(member_id) => resolved.push_str(&format!("<@{}>", member_id)),
+                None => resolved.push_str(email),
+            }
+            rest = &rest[end + 1..];
+        }
+        resolved.push_str(rest);
+        resolved
+    }
+
+    /// Append a span's summary to the root message of its thread in every channel.
+    async fn update_roots(
+        &self,
+        message: &dyn WebhookMessage,
+        thread: &MessageThread,
+    ) -> Result<Delivered, DeliveryError> {
+        let summary: Value = serde_json::from_str(&message.serialize())
+            .map_err(|e| DeliveryError::Rejected(format!("invalid slack message: {}", e)))?;
+        let summary = summary.get("text").and_then(Value::as_str).unwrap_or_default();
+        let mut delivered = Delivered::default();
+        for (_, root) in thread.roots() {
+            let mut payload = root["message"].clone();
+            match payload.get_mut("blocks").and_then(Value::as_array_mut) {
+                Some(blocks) => blocks.push(serde_json::json!({
+                    "type": "context",
+                    "elements": [{ "type": "mrkdwn", "text": summary }],
+                })),
+                None => {
+                    let text = payload.get("text").and_then(Value::as_str).unwrap_or_default();
+                    payload["text"] = Value::String(format!("{}\n{}", text, summary));
+                }
+            }
+            // Messages are updated by the ID of their channel, rather than by the channel's name, and keep
+            // the username and icon they were posted with.
+            if let Some(payload) = payload.as_object_mut() {
+                payload.remove("username");
+                payload.remove("icon_emoji");
+                payload.remove("icon_url");
+            }
+            payload["channel"] = root["channel"].clone();
+            payload["ts"] = root["ts"].clone();
+            delivered = Delivered::with_status(self.call(message.webhook_url(), payload.to_string()).await?.status);
+        }
+        Ok(delivered)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Sink for SlackWebApiSink {
+    fn deliver<'a>(&'a self, message: &'a dyn WebhookMessage) -> DeliveryFuture<'a> {
+        Box::pin(async move {
+            if let (UPDATE_MESSAGE_URL, Some(thread)) = (message.webhook_url(), message.thread()) {
+                return self.update_roots(message, thread).await;
+            }
+
+            let payload_json = self.resolve_email_mentions(message.serialize()).await;
+            let mut payload: Value = serde_json::from_str(&payload_json)
+                .map_err(|e| DeliveryError::Rejected(format!("invalid slack message: {}", e)))?;
+            // Threads are tracked per channel, as thread timestamps are only meaningful within a channel.
+            let channel = payload
+                .get("channel")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            let thread_ts = message
+                .thread()
+                .and_then(|thread| thread.root(&channel))
+                .and_then(|root| root["ts"].as_str().map(str::to_string));
+            if let Some(thread_ts) = &thread_ts {
+                payload["thread_ts"] = Value::String(thread_ts.clone());
+            }
+            let response = self.call(message.webhook_url(), payload.to_string()).await?;
+            let delivered = Delivered::with_status(response.status);
+            let (channel_id, ts) = match (response.channel, response.ts) {
+                (Some(channel_id), Some(ts)) => (channel_id, ts),
+                _ => return Ok(delivered),
+            };
+            if let Some(attachment) = message.attachment() {
+                // The message was already posted, so a failed upload must not be retried.
+                let thread_ts = thread_ts.as_deref().unwrap_or(&ts);
+                if let Err(e) = self.upload(attachment, &channel_id, thread_ts).await {
+                    return Err(DeliveryError::Rejected(format!(
+                        "failed to upload {}: {}",
+                        attachment.filename, e
+                    )));
+                }
+            }
+            if let (Some(thread), None) = (message.thread(), thread_ts) {
+                let root = serde_json::json!({ "channel": channel_id, "ts": ts, "message": payload });
+                thread.set_root(channel, root);
+            }
+            Ok(delivered)
+        })
+    }
+}
@@ -0,0 +1,287 @@
+#![doc = include_str!("../README.md")]
+
+use serde::Serialize;
+use serde_json::Value;
+#[cfg(feature = "signal")]
+pub use tracing_layer_core::drain_on_signal;
+pub use tracing_layer_core::fallback::FallbackWriter;
+pub use tracing_layer_core::filters::EventFilters;
+use tracing_layer_core::layer::WebhookLayerBuilder;
+pub use tracing_layer_core::layer::{FilterHandle, LayerSwitch, WebhookLayer};
+pub use tracing_layer_core::retry::RetryPolicy;
+#[cfg(feature = "async-std")]
+pub use tracing_layer_core::runtime::AsyncStdRuntime;
+#[cfg(feature = "smol")]
+pub use tracing_layer_core::runtime::SmolRuntime;
+#[cfg(feature = "tokio")]
+pub use tracing_layer_core::runtime::TokioRuntime;
+pub use tracing_layer_core::runtime::{Runtime, RuntimeFuture, ThreadRuntime};
+pub use tracing_layer_core::scrub::Scrubber;
+#[cfg(feature = "ureq")]
+pub use tracing_layer_core::sink::BlockingWebhookSink;
+#[cfg(feature = "tokio")]
+pub use tracing_layer_core::sink::WebhookSink;
+pub use tracing_layer_core::sink::{Delivered, DeliveryError, DeliveryFuture, Sink};
+pub use tracing_layer_core::{install_panic_hook, BackgroundWorker, DeliveryReport, WorkerGuard, WorkerMetrics};
+use tracing_layer_core::{Config, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
+
+/// Layer for forwarding tracing events to Microsoft Teams.
+pub struct TeamsLayer;
+
+impl TeamsLayer {
+    pub fn builder(app_name: String, target_filters: EventFilters) -> WebhookLayerBuilder<TeamsConfig, Self> {
+        WebhookLayer::builder(app_name, target_filters)
+    }
+}
+
+impl WebhookMessageFactory<TeamsConfig> for TeamsLayer {
+    fn create(config: &TeamsConfig, inputs: WebhookMessageInputs) -> impl WebhookMessage + 'static {
+        let body = match config.card_format {
+            CardFormat::AdaptiveCard => adaptive_card(&inputs),
+            CardFormat::MessageCard => message_card(&inputs),
+        };
+        TeamsMessagePayload {
+            body,
+            webhook_url: inputs.webhook_url,
+        }
+    }
+}
+
+/// The kind of card posted to Microsoft Teams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardFormat {
+    /// An Adaptive Card, as accepted by Workflows webhooks.
+    AdaptiveCard,
+    /// A legacy connector MessageCard, as accepted by Office 365 connector webhooks.
+    MessageCard,
+}
+
+impl Default for CardFormat {
+    /// Adaptive Cards with the `adaptive-card` feature enabled (the default), MessageCards otherwise.
+    fn default() -> Self {
+        if cfg!(feature = "adaptive-card") {
+            CardFormat::AdaptiveCard
+        } else {
+            CardFormat::MessageCard
+        }
+    }
+}
+
+fn adaptive_card(inputs: &WebhookMessageInputs) -> Value {
+    let event_level_color = match inputs.event_level {
+        tracing::Level::TRACE => "Default",
+        tracing::Level::DEBUG => "Default",
+        tracing::Level::INFO => "Accent",
+        tracing::Level::WARN => "Warning",
+        tracing::Level::ERROR => "Attention",
+    };
+    serde_json::json!({
+        "type": "message",
+        "attachments": [
+            {
+                "contentType": "application/vnd.microsoft.card.adaptive",
+                "content": {
+                    "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                    "type": "AdaptiveCard",
+                    "version": "1.4",
+                    "msteams": { "width": "Full" },
+                    "body": [
+                        {
+                            "type": "TextBlock",
+                            "text": format!("{} - {}", inputs.app_name, inputs.event_level),
+                            "weight": "Bolder",
+                            "color": event_level_color,
+                        },
+                        {
+                            "type": "TextBlock",
+                            "text": inputs.message,
+                            "wrap": true,
+                        },
+                        {
+                            "type": "FactSet",
+                            "facts": [
+                                { "title": "Target Span", "value": format!("{}::{}", inputs.target, inputs.span) },
+                                {
+                                    "title": "Source",
+                                    "value": format!("{}#L{}", inputs.source_file, inputs.source_line),
+                                },
+                            ]
+                        },
+                        {
+                            "type": "TextBlock",
+                            "text": inputs.metadata,
+                            "fontType": "Monospace",
+                            "wrap": true,
+                        },
+                    ]
+                }
+            }
+        ]
+    })
+}
+
+fn message_card(inputs: &WebhookMessageInputs) -> Value {
+    let event_level_color = match inputs.event_level {
+        tracing::Level::TRACE => "808080",
+        tracing::Level::DEBUG => "808080",
+        tracing::Level::INFO => "2EB67D",
+        tracing::Level::WARN => "ECB22E",
+        tracing::Level::ERROR => "E01E5A",
+    };
+    serde_json::json!({
+        "@type": "MessageCard",
+        "@context": "http://schema.org/extensions",
+        "themeColor": event_level_color,
+        "summary": inputs.message,
+        "title": format!("{} - {}", inputs.app_name, inputs.event_level),
+        "sections": [
+            {
+                "text": inputs.message,
+                "facts": [
+                    { "name": "Target Span", "value": format!("{}::{}", inputs.target, inputs.span) },
+                    { "name": "Source", "value": format!("{}#L{}", inputs.source_file, inputs.source_line) },
+                ]
+            },
+            {
+                "text": format!("<pre>{}</pre>", inputs.metadata),
+            }
+        ]
+    })
+}
+
+/// Configuration describing how to forward tracing events to Microsoft Teams.
+pub struct TeamsConfig {
+    pub(crate) webhook_url: String,
+    pub(crate) card_format: CardFormat,
+}
+
+impl TeamsConfig {
+    pub fn new(webhook_url: String) -> Self {
+        Self {
+            webhook_url,
+            card_format: CardFormat::default(),
+        }
+    }
+
+    /// Post this kind of card, instead of the one picked by the `adaptive-card` feature.
+    pub fn card_format(mut self, card_format: CardFormat) -> Self {
+        self.card_format = card_format;
+        self
+    }
+
+    /// Create a new config for forwarding messages to Microsoft Teams using configuration
+    /// available in the environment.
+    ///
+    /// Required env vars:
+    ///   * TEAMS_WEBHOOK_URL
+    pub fn new_from_env() -> Self {
+        Self::new(std::env::var("TEAMS_WEBHOOK_URL").expect("teams webhook url in env"))
+    }
+}
+
+impl Default for TeamsConfig {
+    fn default() -> Self {
+        Self::new_from_env()
+    }
+}
+
+impl Config for TeamsConfig {
+    fn webhook_url(&self) -> &str {
+        &self.webhook_url
+    }
+
+    fn new_from_env() -> Self
+    where
+        Self: Sized,
+    {
+        Self::new_from_env()
+    }
+}
+
+/// The message sent to Microsoft Teams. The logged record being "drained" will be
+/// converted into this format.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct TeamsMessagePayload {
+    #[serde(flatten)]
+    body: Value,
+    #[serde(skip_serializing)]
+    webhook_url: String,
+}
+
+impl WebhookMessage for TeamsMessagePayload {
+    fn webhook_url(&self) -> &str {
+        self.webhook_url.as_str()
+    }
+
+    fn serialize(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize teams message")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tracing::Level;
+
+    use super::*;
+
+    fn inputs(message: &str, event_level: Level) -> WebhookMessageInputs {
+        WebhookMessageInputs {
+            app_name: "app".to_string(),
+            message: message.to_string(),
+            target: "app::billing".to_string(),
+            span: "charge".to_string(),
+            metadata: "{}".to_string(),
+            fields: HashMap::new(),
+            rendered_fields: HashMap::new(),
+            reserved_fields: HashMap::new(),
+            webhook_url: "https://example.webhook.office.com/webhook".to_string(),
+            source_line: 1,
+            source_file: "src/main.rs".to_string(),
+            event_level,
+            thread: None,
+            span_trace: None,
+            backtrace: None,
+        }
+    }
+
+    fn render(card_format: CardFormat, inputs: WebhookMessageInputs) -> Value {
+        let config =
+            TeamsConfig::new("https://example.webhook.office.com/webhook".to_string()).card_format(card_format);
+        let message = TeamsLayer::create(&config, inputs);
+        assert_eq!(message.webhook_url(), "https://example.webhook.office.com/webhook");
+        serde_json::from_str(&message.serialize()).unwrap()
+    }
+
+    #[test]
+    fn message_card_serialization() {
+        let card = render(CardFormat::MessageCard, inputs("payment failed", Level::ERROR));
+        assert_eq!(card["@type"], "MessageCard");
+        assert_eq!(card["themeColor"], "E01E5A");
+        assert_eq!(card["summary"], "payment failed");
+        assert_eq!(card["title"], "app - ERROR");
+        assert_eq!(card["sections"][0]["text"], "payment failed");
+        assert_eq!(card["sections"][0]["facts"][0]["value"], "app::billing::charge");
+        assert_eq!(card["sections"][0]["facts"][1]["value"], "src/main.rs#L1");
+        assert_eq!(card["sections"][1]["text"], "<pre>{}</pre>");
+        assert!(card.get("webhook_url").is_none());
+    }
+
+    #[test]
+    fn adaptive_card_serialization() {
+        let card = render(CardFormat::AdaptiveCard, inputs("disk almost full", Level::WARN));
+        assert_eq!(card["type"], "message");
+        let attachment = &card["attachments"][0];
+        assert_eq!(attachment["contentType"], "application/vnd.microsoft.card.adaptive");
+        let content = &attachment["content"];
+        assert_eq!(content["type"], "AdaptiveCard");
+        assert_eq!(content["version"], "1.4");
+        assert_eq!(content["body"][0]["text"], "app - WARN");
+        assert_eq!(content["body"][0]["color"], "Warning");
+        assert_eq!(content["body"][1]["text"], "disk almost full");
+        assert_eq!(content["body"][2]["facts"][0]["value"], "app::billing::charge");
+        assert_eq!(content["body"][3]["fontType"], "Monospace");
+        assert!(card.get("webhook_url").is_none());
+    }
+}
@@ -0,0 +1,257 @@
+#![doc = include_str!("../README.md")]
+
+use serde::Serialize;
+#[cfg(feature = "signal")]
+pub use tracing_layer_core::drain_on_signal;
+pub use tracing_layer_core::fallback::FallbackWriter;
+pub use tracing_layer_core::filters::EventFilters;
+use tracing_layer_core::layer::WebhookLayerBuilder;
+pub use tracing_layer_core::layer::{FilterHandle, LayerSwitch, WebhookLayer};
+pub use tracing_layer_core::retry::RetryPolicy;
+#[cfg(feature = "async-std")]
+pub use tracing_layer_core::runtime::AsyncStdRuntime;
+#[cfg(feature = "smol")]
+pub use tracing_layer_core::runtime::SmolRuntime;
+#[cfg(feature = "tokio")]
+pub use tracing_layer_core::runtime::TokioRuntime;
+pub use tracing_layer_core::runtime::{Runtime, RuntimeFuture, ThreadRuntime};
+pub use tracing_layer_core::scrub::Scrubber;
+#[cfg(feature = "ureq")]
+pub use tracing_layer_core::sink::BlockingWebhookSink;
+#[cfg(feature = "tokio")]
+pub use tracing_layer_core::sink::WebhookSink;
+pub use tracing_layer_core::sink::{Delivered, DeliveryError, DeliveryFuture, Sink};
+pub use tracing_layer_core::{install_panic_hook, BackgroundWorker, DeliveryReport, WorkerGuard, WorkerMetrics};
+use tracing_layer_core::{Config, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
+
+/// Maximum number of characters allowed in the text of a Telegram message.
+const MAX_MESSAGE_CHARS: usize = 4096;
+
+/// Layer for forwarding tracing events to a Telegram chat.
+pub struct TelegramLayer;
+
+impl TelegramLayer {
+    pub fn builder(app_name: String, target_filters: EventFilters) -> WebhookLayerBuilder<TelegramConfig, Self> {
+        WebhookLayer::builder(app_name, target_filters)
+    }
+}
+
+impl WebhookMessageFactory<TelegramConfig> for TelegramLayer {
+    fn create(config: &TelegramConfig, inputs: WebhookMessageInputs) -> impl WebhookMessage + 'static {
+        let event_level_emoji = match inputs.event_level {
+            tracing::Level::TRACE => "🔍",
+            tracing::Level::DEBUG => "🐛",
+            tracing::Level::INFO => "ℹ️",
+            tracing::Level::WARN => "⚠️",
+            tracing::Level::ERROR => "❌",
+        };
+        // Truncate each part of the message to what is left of Telegram's limit, giving the headline
+        // precedence over the metadata. The parts are escaped before being truncated, so the truncation
+        // never splits an entity.
+        const TEMPLATE_CHARS: usize = concat!(
+            "<b> -  </b>\n",
+            "<i></i>\n\n",
+            "<b>Target Span</b>: <code>::</code>\n",
+            "<b>Source</b>: <code>#L</code>\n",
+            "<pre></pre>",
+        )
+        .len();
+        const MAX_LABEL_CHARS: usize = 256;
+        let event_level = inputs.event_level.to_string();
+        let source_line = inputs.source_line.to_string();
+        let mut remaining = MAX_MESSAGE_CHARS
+            .saturating_sub(TEMPLATE_CHARS + event_level_emoji.chars().count() + event_level.len() + source_line.len());
+        let mut take = |value: &str, max_chars: usize| {
+            let escaped = escape_html_truncated(value, remaining.min(max_chars));
+            remaining -= escaped.chars().count();
+            escaped
+        };
+        let app_name = take(&inputs.app_name, MAX_LABEL_CHARS);
+        let source_file = take(&inputs.source_file, MAX_LABEL_CHARS);
+        let target = take(&inputs.target, MAX_LABEL_CHARS);
+        let span = take(&inputs.span, MAX_LABEL_CHARS);
+        let message = take(&inputs.message, usize::MAX);
+        let metadata = take(&inputs.metadata, usize::MAX);
+
+        let text = format!(
+            concat!(
+                "<b>{} - {} {}</b>\n",
+                "<i>{}</i>\n\n",
+                "<b>Target Span</b>: <code>{}::{}</code>\n",
+                "<b>Source</b>: <code>{}#L{}</code>\n",
+                "<pre>{}</pre>",
+            ),
+            app_name, event_level_emoji, event_level, message, target, span, source_file, source_line, metadata,
+        );
+
+        TelegramMessagePayload {
+            chat_id: config.chat_id.clone(),
+            text,
+            parse_mode: "HTML",
+            webhook_url: inputs.webhook_url,
+        }
+    }
+}
+
+/// Escape the characters which Telegram's HTML parse mode treats as markup, keeping at most `max_chars`
+/// characters of the escaped value without cutting an entity in half.
+fn escape_html_truncated(value: &str, max_chars: usize) -> String {
+    let mut escaped = String::new();
+    let mut char_count = 0;
+    for c in value.chars() {
+        let entity = match c {
+            '&' => "&amp;",
+            '<' => "&lt;",
+            '>' => "&gt;",
+            _ => "",
+        };
+        let chars = if entity.is_empty() { 1 } else { entity.len() };
+        if char_count + chars > max_chars {
+            break;
+        }
+        char_count += chars;
+        if entity.is_empty() {
+            escaped.push(c);
+        } else {
+            escaped.push_str(entity);
+        }
+    }
+    escaped
+}
+
+/// Configuration describing how to forward tracing events to a Telegram chat via the Bot API.
+pub struct TelegramConfig {
+    pub(crate) webhook_url: String,
+    pub(crate) chat_id: String,
+}
+
+impl TelegramConfig {
+    pub fn new(bot_token: String, chat_id: String) -> Self {
+        Self {
+            webhook_url: format!("https://api.telegram.org/bot{}/sendMessage", bot_token),
+            chat_id,
+        }
+    }
+
+    /// Create a new config for forwarding messages to Telegram using configuration
+    /// available in the environment.
+    ///
+    /// Required env vars:
+    ///   * TELEGRAM_BOT_TOKEN
+    ///   * TELEGRAM_CHAT_ID
+    pub fn new_from_env() -> Self {
+        Self::new(
+            std::env::var("TELEGRAM_BOT_TOKEN").expect("telegram bot token in env"),
+            std::env::var("TELEGRAM_CHAT_ID").expect("telegram chat id in env"),
+        )
+    }
+}
+
+impl Default for TelegramConfig {
+    fn default() -> Self {
+        Self::new_from_env()
+    }
+}
+
+impl Config for TelegramConfig {
+    fn webhook_url(&self) -> &str {
+        &self.webhook_url
+    }
+
+    fn new_from_env() -> Self
+    where
+        Self: Sized,
+    {
+        Self::new_from_env()
+    }
+}
+
+/// The message sent to Telegram's `sendMessage` method. The logged record being "drained" will be
+/// converted into this format.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct TelegramMessagePayload {
+    chat_id: String,
+    text: String,
+    parse_mode: &'static str,
+    #[serde(skip_serializing)]
+    webhook_url: String,
+}
+
+impl WebhookMessage for TelegramMessagePayload {
+    fn webhook_url(&self) -> &str {
+        self.webhook_url.as_str()
+    }
+
+    fn serialize(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize telegram message")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tracing::Level;
+
+    use super::*;
+
+    fn inputs(message: String, target: String, metadata: String) -> WebhookMessageInputs {
+        WebhookMessageInputs {
+            app_name: "app".to_string(),
+            message,
+            target,
+            span: "charge".to_string(),
+            metadata,
+            fields: HashMap::new(),
+            rendered_fields: HashMap::new(),
+            reserved_fields: HashMap::new(),
+            webhook_url: String::new(),
+            source_line: 1,
+            source_file: "src/main.rs".to_string(),
+            event_level: Level::ERROR,
+            thread: None,
+            span_trace: None,
+            backtrace: None,
+        }
+    }
+
+    fn text(inputs: WebhookMessageInputs) -> String {
+        let config = TelegramConfig::new("token".to_string(), "42".to_string());
+        let payload: serde_json::Value =
+            serde_json::from_str(&TelegramLayer::create(&config, inputs).serialize()).unwrap();
+        payload["text"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn short_messages_are_not_truncated() {
+        let text = text(inputs(
+            "a < b".to_string(),
+            "app::billing".to_string(),
+            "{}".to_string(),
+        ));
+        assert!(text.contains("<i>a &lt; b</i>"));
+        assert!(text.contains("<code>app::billing::charge</code>"));
+        assert!(text.ends_with("<pre>{}</pre>"));
+    }
+
+    #[test]
+    fn long_headers_are_truncated_to_the_message_limit() {
+        let message = "<&>".repeat(2000);
+        let target = "t".repeat(5000);
+        let text = text(inputs(message, target, "x".repeat(5000)));
+        assert!(text.chars().count() <= MAX_MESSAGE_CHARS);
+        assert!(text.ends_with("<pre></pre>"));
+        // The message is cut between entities, never inside one.
+        let headline = &text[text.find("<i>").unwrap() + 3..text.find("</i>").unwrap()];
+        assert!(!headline.is_empty());
+        assert_eq!(
+            headline
+                .replace("&lt;&amp;&gt;", "")
+                .replace("&lt;&amp;", "")
+                .replace("&lt;", ""),
+            ""
+        );
+        // Labels are capped, leaving room for the message.
+        assert!(text.contains(&format!("<code>{}::charge</code>", "t".repeat(256))));
+    }
+}